@@ -0,0 +1,415 @@
+//! Drives a node by hand. Spawns the node binary (or, with `--sim N`, an in-process
+//! simulated cluster of N nodes), reads short commands from stdin, sends the Maelstrom
+//! message they stand for and prints what comes back, tied to the request it answers.
+//!
+//!     cargo run --bin repl
+//!     > init n1 n1,n2,n3
+//!     > broadcast 5
+//!     > read n2
+
+use std::{
+    collections::HashMap,
+    env,
+    io::{self, BufRead, BufReader, Write},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::{Duration, Instant},
+};
+
+use rusty_glomers::{
+    config::Config,
+    message::{MaelstromMessage, MaelstromMessageBody, MessageType, MicroOp},
+    node_id::NodeId,
+    sim::Simulator,
+};
+
+const RESET: &str = "\x1b[0m";
+const DIM: &str = "\x1b[2m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const CYAN: &str = "\x1b[36m";
+
+/// How long to wait for a reply before giving the prompt back.
+const REPLY_TIMEOUT: Duration = Duration::from_millis(500);
+/// How long to keep collecting after the reply, for anything else it set off.
+const SETTLE: Duration = Duration::from_millis(50);
+
+const HELP: &str = "\
+commands, each optionally naming the node to send to (e.g. `read n2`):
+  init <node> <n1,n2,...>    init a node (the whole cluster with --sim)
+  topology                   full mesh over the nodes from init
+  echo <text>
+  generate
+  broadcast <value>
+  read [key]
+  write <key> <value>
+  cas <key> <from> <to>
+  add <element or delta>
+  txn <op>...                ops like r1 w1=5
+  metrics
+  debug <state|peers|pending>
+  raw <json body>            any body, msg_id filled in
+  wait <ms>                  collect output for a while
+  help
+  quit";
+
+enum Target {
+    Process {
+        child: Child,
+        stdin: ChildStdin,
+        stdout: Receiver<MaelstromMessage>,
+    },
+    Sim(Box<Simulator>),
+}
+
+impl Target {
+    fn spawn() -> Self {
+        let bin = env::var("GLOMERS_BIN").unwrap_or_else(|_| {
+            let exe = env::current_exe().unwrap();
+            exe.with_file_name("rusty-glomers").display().to_string()
+        });
+        let mut child = Command::new(&bin)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap_or_else(|e| panic!("couldn't start {}: {}", bin, e));
+        let stdin = child.stdin.take().unwrap();
+        let out = child.stdout.take().unwrap();
+        let (tx, stdout) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(out).lines().map_while(Result::ok) {
+                match serde_json::from_str(&line) {
+                    Ok(msg) => {
+                        if tx.send(msg).is_err() {
+                            return;
+                        }
+                    }
+                    Err(_) => println!("{}{}{}", RED, line, RESET),
+                }
+            }
+        });
+        Target::Process {
+            child,
+            stdin,
+            stdout,
+        }
+    }
+
+    /// Sends `body` to `dest` and returns everything that came out until the reply to it
+    /// (and a little after), or until the timeout. Also returns the msg_id it went out
+    /// with, which the simulator picks itself.
+    fn send(
+        &mut self,
+        dest: &NodeId,
+        body: MaelstromMessageBody,
+    ) -> (usize, Vec<MaelstromMessage>) {
+        match self {
+            Target::Process { stdin, stdout, .. } => {
+                let msg_id = body.msg_id;
+                let msg = MaelstromMessage {
+                    src: Simulator::CLIENT,
+                    dest: dest.clone(),
+                    body,
+                };
+                writeln!(stdin, "{}", msg).unwrap();
+                let mut out = Vec::new();
+                let mut deadline = Instant::now() + REPLY_TIMEOUT;
+                while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+                    let Ok(msg) = stdout.recv_timeout(left) else {
+                        break;
+                    };
+                    if msg.body.in_reply_to == msg_id && msg.dest == Simulator::CLIENT {
+                        deadline = deadline.min(Instant::now() + SETTLE);
+                    }
+                    out.push(msg);
+                }
+                (msg_id.unwrap(), out)
+            }
+            Target::Sim(sim) => {
+                let seen = sim.replies.len();
+                let msg_id = sim.request(dest, body);
+                let step = Duration::from_millis(1);
+                let mut waited = Duration::ZERO;
+                while sim.reply_to(msg_id).is_none() && waited < REPLY_TIMEOUT {
+                    sim.run_for(step);
+                    waited += step;
+                }
+                sim.run_for(SETTLE);
+                (msg_id, sim.replies[seen..].to_vec())
+            }
+        }
+    }
+
+    fn wait(&mut self, duration: Duration) -> Vec<MaelstromMessage> {
+        match self {
+            Target::Process { stdout, .. } => {
+                let deadline = Instant::now() + duration;
+                let mut out = Vec::new();
+                while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+                    match stdout.recv_timeout(left) {
+                        Ok(msg) => out.push(msg),
+                        Err(_) => break,
+                    }
+                }
+                out
+            }
+            Target::Sim(sim) => {
+                let seen = sim.replies.len();
+                sim.run_for(duration);
+                sim.replies[seen..].to_vec()
+            }
+        }
+    }
+}
+
+struct Repl {
+    target: Target,
+    node_ids: Vec<NodeId>,
+    /// the node commands go to when they don't name one
+    default_node: NodeId,
+    next_msg_id: usize,
+    /// msg_id -> the command that sent it
+    sent: HashMap<usize, String>,
+}
+
+impl Repl {
+    fn node_id(arg: &str) -> Option<NodeId> {
+        arg.parse().ok().filter(NodeId::is_node)
+    }
+
+    /// Runs one line. Returns false to quit.
+    fn run(&mut self, line: &str) -> bool {
+        let mut args: Vec<&str> = line.split_whitespace().collect();
+        let Some(command) = args.first().copied() else {
+            return true;
+        };
+        args.remove(0);
+        let body = match command {
+            "quit" | "exit" => return false,
+            "help" => {
+                println!("{}", HELP);
+                return true;
+            }
+            "wait" => {
+                let ms = args.first().and_then(|x| x.parse().ok()).unwrap_or(100);
+                let out = self.target.wait(Duration::from_millis(ms));
+                out.iter().for_each(|x| self.print(x));
+                return true;
+            }
+            "init" => return self.init(&args),
+            _ => {
+                // the first bare node id is where it goes, the rest are the arguments
+                let node = args.iter().position(|x| Repl::node_id(x).is_some());
+                if let Some(i) = node {
+                    self.default_node = Repl::node_id(args.remove(i)).unwrap();
+                }
+                match self.body(command, &args) {
+                    Ok(body) => body,
+                    Err(e) => {
+                        println!("{}{}{}", RED, e, RESET);
+                        return true;
+                    }
+                }
+            }
+        };
+        let dest = self.default_node.clone();
+        self.send(&dest, body, line);
+        true
+    }
+
+    fn init(&mut self, args: &[&str]) -> bool {
+        if let Target::Sim(sim) = &mut self.target {
+            sim.init();
+            self.node_ids = sim.node_ids();
+            let node_ids: Vec<String> = self.node_ids.iter().map(|x| x.to_string()).collect();
+            println!("{}initialised {}{}", GREEN, node_ids.join(","), RESET);
+            return true;
+        }
+        let node_id = args.first().and_then(|x| Repl::node_id(x));
+        let node_ids = args
+            .get(1)
+            .and_then(|x| x.split(',').map(Repl::node_id).collect::<Option<Vec<_>>>());
+        let (Some(node_id), Some(node_ids)) = (node_id, node_ids) else {
+            println!("{}usage: init <node> <n1,n2,...>{}", RED, RESET);
+            return true;
+        };
+        self.default_node = node_id.clone();
+        self.node_ids = node_ids;
+        let body = MaelstromMessageBody {
+            node_id: Some(node_id.clone()),
+            node_ids: Some(self.node_ids.clone()),
+            ..MaelstromMessageBody::new(MessageType::Init)
+        };
+        self.send(&node_id, body, &format!("init {}", node_id));
+        true
+    }
+
+    /// The body a command stands for.
+    fn body(&self, command: &str, args: &[&str]) -> Result<MaelstromMessageBody, String> {
+        let num = |i: usize| -> Result<usize, String> {
+            args.get(i)
+                .ok_or(format!("{} needs more arguments, see help", command))?
+                .parse()
+                .map_err(|e| format!("{}: {}", args[i], e))
+        };
+        let body = match command {
+            "topology" => MaelstromMessageBody {
+                topology: Some(
+                    self.node_ids
+                        .iter()
+                        .map(|x| {
+                            let others = self.node_ids.iter().filter(|y| *y != x).cloned();
+                            (x.clone(), others.collect())
+                        })
+                        .collect(),
+                ),
+                ..MaelstromMessageBody::new(MessageType::Topology)
+            },
+            "echo" => MaelstromMessageBody {
+                echo: Some(args.join(" ")),
+                ..MaelstromMessageBody::new(MessageType::Echo)
+            },
+            "generate" => MaelstromMessageBody::new(MessageType::Generate),
+            "broadcast" => MaelstromMessageBody {
+                message: Some(num(0)?),
+                ..MaelstromMessageBody::new(MessageType::Broadcast)
+            },
+            "read" => MaelstromMessageBody {
+                key: args.first().map(|_| num(0)).transpose()?,
+                ..MaelstromMessageBody::new(MessageType::Read)
+            },
+            "write" => MaelstromMessageBody {
+                key: Some(num(0)?),
+                value: Some(num(1)?.into()),
+                ..MaelstromMessageBody::new(MessageType::Write)
+            },
+            "cas" => MaelstromMessageBody {
+                key: Some(num(0)?),
+                from: Some(num(1)?),
+                to: Some(num(2)?),
+                ..MaelstromMessageBody::new(MessageType::Cas)
+            },
+            "add" => {
+                let arg = args.first().ok_or("add needs a value")?;
+                let delta: i64 = arg.parse().map_err(|e| format!("{}: {}", arg, e))?;
+                MaelstromMessageBody {
+                    // the g-set takes elements, the counters deltas; the node uses whichever
+                    element: usize::try_from(delta).ok(),
+                    delta: Some(delta),
+                    ..MaelstromMessageBody::new(MessageType::Add)
+                }
+            }
+            "txn" => MaelstromMessageBody {
+                txn: Some(
+                    args.iter()
+                        .map(|x| Repl::micro_op(x))
+                        .collect::<Result<_, _>>()?,
+                ),
+                ..MaelstromMessageBody::new(MessageType::Txn)
+            },
+            "metrics" => MaelstromMessageBody::new(MessageType::Metrics),
+            "debug" => MaelstromMessageBody::new(match args.first().copied() {
+                Some("state") => MessageType::DebugState,
+                Some("peers") => MessageType::DebugPeers,
+                Some("pending") => MessageType::DebugPending,
+                _ => return Err(String::from("debug <state|peers|pending>")),
+            }),
+            "raw" => serde_json::from_str(&args.join(" ")).map_err(|e| e.to_string())?,
+            _ => return Err(format!("unknown command {}, try help", command)),
+        };
+        Ok(body)
+    }
+
+    /// `r1` reads key 1, `w1=5` writes 5 to it.
+    fn micro_op(arg: &str) -> Result<MicroOp, String> {
+        let bad = || format!("bad txn op {}, expected r<key> or w<key>=<value>", arg);
+        let (f, rest) = arg.split_at(1.min(arg.len()));
+        match (f, rest.split_once('=')) {
+            ("r", None) => Ok(MicroOp::Read(rest.parse().map_err(|_| bad())?, None)),
+            ("w", Some((key, value))) => Ok(MicroOp::Write(
+                key.parse().map_err(|_| bad())?,
+                value.parse().map_err(|_| bad())?,
+            )),
+            _ => Err(bad()),
+        }
+    }
+
+    fn send(&mut self, dest: &NodeId, body: MaelstromMessageBody, line: &str) {
+        let body = MaelstromMessageBody {
+            msg_id: Some(self.next_msg_id),
+            ..body
+        };
+        self.next_msg_id += 1;
+        let (msg_id, out) = self.target.send(dest, body);
+        self.sent.insert(msg_id, line.to_string());
+        println!("{}-> {} #{} {}{}", DIM, dest, msg_id, line, RESET);
+        out.iter().for_each(|x| self.print(x));
+    }
+
+    /// Replies to us in green (red for errors) under the command they answer, anything
+    /// a node sent to another node in cyan.
+    fn print(&self, msg: &MaelstromMessage) {
+        let body = serde_json::to_value(&msg.body).unwrap();
+        let pretty = serde_json::to_string_pretty(&body).unwrap();
+        if msg.dest != Simulator::CLIENT {
+            let msg_type = body["type"].as_str().unwrap_or_default();
+            println!(
+                "{}   {} -> {} {}{}",
+                CYAN, msg.src, msg.dest, msg_type, RESET
+            );
+            return;
+        }
+        let colour = match msg.body.msg_type {
+            MessageType::Error => RED,
+            _ if msg.body.in_reply_to.is_some() => GREEN,
+            _ => YELLOW,
+        };
+        let answers = msg
+            .body
+            .in_reply_to
+            .and_then(|x| self.sent.get(&x).map(|line| format!(" (#{} {})", x, line)))
+            .unwrap_or_default();
+        println!("{}<- {}{}{}", colour, msg.src, answers, RESET);
+        println!("{}{}{}", colour, pretty, RESET);
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let sim = args
+        .iter()
+        .position(|x| x == "--sim")
+        .map(|i| args.get(i + 1).and_then(|x| x.parse().ok()).unwrap_or(3));
+    let (target, default_node) = match sim {
+        Some(nodes) => (
+            Target::Sim(Box::new(Simulator::new(nodes, Config::from_env()))),
+            0,
+        ),
+        None => (Target::spawn(), 1),
+    };
+    let mut repl = Repl {
+        target,
+        node_ids: Vec::new(),
+        default_node: NodeId::Node(default_node),
+        next_msg_id: 1,
+        sent: HashMap::new(),
+    };
+    if let Target::Sim(sim) = &repl.target {
+        println!("{}simulator seed {}{}", DIM, sim.seed, RESET);
+    }
+    println!("{}type help for commands{}", DIM, RESET);
+    let stdin = io::stdin();
+    loop {
+        print!("{}> ", repl.default_node);
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 || !repl.run(line.trim()) {
+            break;
+        }
+    }
+    if let Target::Process { mut child, .. } = repl.target {
+        let _ = child.kill();
+    }
+}
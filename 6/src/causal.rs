@@ -0,0 +1,122 @@
+use serde::Serialize;
+
+use crate::{clocks::VectorClock, node_id::NodeId};
+
+/// A broadcast value that has arrived but can't be shown yet.
+#[derive(Serialize, Debug, Clone)]
+pub struct Pending {
+    pub origin: NodeId,
+    pub deps: VectorClock,
+    pub message: usize,
+}
+
+/// Causal broadcast: every value carries the vector of broadcasts its origin had
+/// delivered when it was sent, and only becomes visible once this node has delivered all
+/// of those too. `delivered` counts the values delivered from each origin, so a value's
+/// entry for its own origin doubles as its sequence number there.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct CausalBroadcast {
+    pub delivered: VectorClock,
+    pub pending: Vec<Pending>,
+}
+
+impl CausalBroadcast {
+    /// Delivers a value broadcast from this node, returning the deps to send it with.
+    pub fn broadcast(&mut self, node_id: &NodeId) -> VectorClock {
+        let deps = self.delivered.clone();
+        self.delivered.increment(node_id);
+        deps
+    }
+
+    /// Whether a value sent with `deps` hasn't been delivered or buffered here already.
+    pub fn is_new(&self, origin: &NodeId, deps: &VectorClock) -> bool {
+        deps.get(origin) >= self.delivered.get(origin)
+            && !self
+                .pending
+                .iter()
+                .any(|x| x.origin == *origin && x.deps.get(origin) == deps.get(origin))
+    }
+
+    /// Takes a value from another node and returns whatever can now be delivered, it
+    /// included, in an order that respects causality.
+    pub fn receive(&mut self, origin: NodeId, deps: VectorClock, message: usize) -> Vec<usize> {
+        if !self.is_new(&origin, &deps) {
+            return Vec::new();
+        }
+        self.pending.push(Pending {
+            origin,
+            deps,
+            message,
+        });
+        let mut delivered = Vec::new();
+        while let Some(i) = self.pending.iter().position(|x| self.deliverable(x)) {
+            let next = self.pending.remove(i);
+            self.delivered.increment(&next.origin);
+            delivered.push(next.message);
+        }
+        delivered
+    }
+
+    /// Next in line from its origin, and nothing it depends on is missing.
+    fn deliverable(&self, pending: &Pending) -> bool {
+        pending.deps.get(&pending.origin) == self.delivered.get(&pending.origin)
+            && pending.deps <= self.delivered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        config::{BroadcastMode, Config},
+        sim::cluster,
+    };
+
+    #[test]
+    fn holds_back_values_until_their_deps_arrive() {
+        let (n0, n1) = (NodeId::Node(0), NodeId::Node(1));
+        // n0 broadcasts 1 then 2, n1 sees 1 and broadcasts 3
+        let mut origin = CausalBroadcast::default();
+        let deps_1 = origin.broadcast(&n0);
+        let deps_2 = origin.broadcast(&n0);
+        let mut relay = CausalBroadcast::default();
+        assert_eq!(relay.receive(n0.clone(), deps_1.clone(), 1), [1]);
+        let deps_3 = relay.broadcast(&n1);
+
+        let mut node = CausalBroadcast::default();
+        assert!(node.receive(n1.clone(), deps_3.clone(), 3).is_empty());
+        assert!(node.receive(n0.clone(), deps_2.clone(), 2).is_empty());
+        assert!(!node.is_new(&n0, &deps_2));
+        assert_eq!(node.receive(n0.clone(), deps_1.clone(), 1), [1, 3, 2]);
+        assert!(node.receive(n0, deps_1, 1).is_empty());
+        assert!(node.pending.is_empty());
+    }
+
+    #[test]
+    fn keeps_each_origins_order_in_a_cluster() {
+        let mut sim = cluster::start(
+            5,
+            Config {
+                broadcast: BroadcastMode::Causal,
+                ..Config::default()
+            },
+        );
+        sim.jitter = Duration::from_millis(20);
+        for message in 0..20 {
+            cluster::broadcast(&mut sim, &NodeId::Node(message % 2), message);
+            sim.run_for(Duration::from_millis(1));
+        }
+        sim.run_for(Duration::from_millis(500));
+        for (node_id, read) in sim.node_ids().iter().zip(cluster::read_all(&mut sim)) {
+            let messages = read.messages.unwrap();
+            assert_eq!(messages.len(), 20, "{}", node_id);
+            for origin in 0..2 {
+                let from_origin: Vec<&usize> =
+                    messages.iter().filter(|x| *x % 2 == origin).collect();
+                assert!(from_origin.windows(2).all(|x| x[0] < x[1]), "{}", node_id);
+            }
+        }
+    }
+}
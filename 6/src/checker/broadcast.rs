@@ -0,0 +1,201 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Duration,
+};
+
+use crate::{
+    history::{History, Outcome},
+    message::MessageType,
+    node_id::NodeId,
+};
+
+/// What the broadcast checker found. A run is valid if nothing acknowledged was lost and
+/// nothing was read that was never broadcast.
+#[derive(Debug, Clone, Default)]
+pub struct BroadcastReport {
+    pub attempt_count: usize,
+    pub acknowledged_count: usize,
+    /// in every node's final read
+    pub stable_count: usize,
+    /// acknowledged but missing from at least one node's final read
+    pub lost: Vec<usize>,
+    /// broadcast but never in any read at all
+    pub never_read: Vec<usize>,
+    /// read but never broadcast
+    pub phantom: Vec<usize>,
+    /// for each stable value, broadcast invocation until every read from then on has it
+    pub stable_latencies: Vec<Duration>,
+}
+
+impl BroadcastReport {
+    pub fn valid(&self) -> bool {
+        self.lost.is_empty() && self.phantom.is_empty()
+    }
+
+    /// `p` in 0..=1, e.g. 0.5 for the median.
+    pub fn latency_quantile(&self, p: f64) -> Option<Duration> {
+        let mut sorted = self.stable_latencies.clone();
+        sorted.sort();
+        let i = ((sorted.len() as f64 - 1.0) * p).round() as usize;
+        sorted.get(i).copied()
+    }
+}
+
+/// Checks a broadcast run. Every node should have been read once the cluster settled,
+/// since the last read of each node is what values are judged on.
+pub fn check(history: &History) -> BroadcastReport {
+    let ops = history.ops();
+    let mut attempted: BTreeMap<usize, Duration> = BTreeMap::new();
+    let mut acknowledged: BTreeSet<usize> = BTreeSet::new();
+    // (time, node, values) for each successful read
+    let mut reads: Vec<(Duration, NodeId, BTreeSet<usize>)> = Vec::new();
+    for op in ops.iter() {
+        match op.request.msg_type {
+            MessageType::Broadcast => {
+                let message = op.request.message.unwrap();
+                attempted.entry(message).or_insert(op.invoke);
+                if op.outcome() == Outcome::Ok {
+                    acknowledged.insert(message);
+                }
+            }
+            MessageType::Read if op.outcome() == Outcome::Ok => {
+                let reply = op.reply.as_ref().unwrap();
+                let values = reply.messages.clone().unwrap_or_default();
+                reads.push((
+                    op.complete.unwrap(),
+                    op.node.clone(),
+                    values.into_iter().collect(),
+                ));
+            }
+            _ => {}
+        }
+    }
+    reads.sort_by_key(|x| x.0);
+
+    let mut final_reads: BTreeMap<NodeId, &BTreeSet<usize>> = BTreeMap::new();
+    for (_, node, values) in reads.iter() {
+        final_reads.insert(node.clone(), values);
+    }
+    let ever_read: BTreeSet<usize> = reads.iter().flat_map(|x| x.2.iter().copied()).collect();
+
+    let mut report = BroadcastReport {
+        attempt_count: attempted.len(),
+        acknowledged_count: acknowledged.len(),
+        ..BroadcastReport::default()
+    };
+    report.phantom = ever_read
+        .iter()
+        .filter(|x| !attempted.contains_key(x))
+        .copied()
+        .collect();
+    for (value, invoked) in attempted.iter() {
+        if !ever_read.contains(value) {
+            report.never_read.push(*value);
+        }
+        let stable = !final_reads.is_empty() && final_reads.values().all(|x| x.contains(value));
+        if !stable {
+            if acknowledged.contains(value) {
+                report.lost.push(*value);
+            }
+            continue;
+        }
+        report.stable_count += 1;
+        let last_missing = reads
+            .iter()
+            .rposition(|(time, _, values)| *time >= *invoked && !values.contains(value));
+        let stable_at = match last_missing {
+            Some(i) => reads[i + 1..].iter().find(|x| x.2.contains(value)),
+            None => reads
+                .iter()
+                .find(|(time, _, values)| *time >= *invoked && values.contains(value)),
+        };
+        if let Some((time, _, _)) = stable_at {
+            report.stable_latencies.push(time.saturating_sub(*invoked));
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{MaelstromMessage, MaelstromMessageBody};
+
+    fn msg(src: &str, dest: &str, body: MaelstromMessageBody) -> MaelstromMessage {
+        MaelstromMessage {
+            src: src.parse().unwrap(),
+            dest: dest.parse().unwrap(),
+            body,
+        }
+    }
+
+    fn broadcast(history: &mut History, at: u64, msg_id: usize, value: usize) {
+        history.push(
+            Duration::from_millis(at),
+            msg(
+                "c1",
+                "n0",
+                MaelstromMessageBody {
+                    msg_id: Some(msg_id),
+                    message: Some(value),
+                    ..MaelstromMessageBody::new(MessageType::Broadcast)
+                },
+            ),
+        );
+        history.push(
+            Duration::from_millis(at + 1),
+            msg(
+                "n0",
+                "c1",
+                MaelstromMessageBody {
+                    in_reply_to: Some(msg_id),
+                    ..MaelstromMessageBody::new(MessageType::BroadcastOk)
+                },
+            ),
+        );
+    }
+
+    fn read(history: &mut History, at: u64, msg_id: usize, node: &str, values: Vec<usize>) {
+        history.push(
+            Duration::from_millis(at),
+            msg(
+                "c2",
+                node,
+                MaelstromMessageBody {
+                    msg_id: Some(msg_id),
+                    ..MaelstromMessageBody::new(MessageType::Read)
+                },
+            ),
+        );
+        history.push(
+            Duration::from_millis(at + 1),
+            msg(
+                node,
+                "c2",
+                MaelstromMessageBody {
+                    in_reply_to: Some(msg_id),
+                    messages: Some(values),
+                    ..MaelstromMessageBody::new(MessageType::ReadOk)
+                },
+            ),
+        );
+    }
+
+    #[test]
+    fn reports_lost_and_phantom_values() {
+        let mut history = History::default();
+        broadcast(&mut history, 0, 0, 1);
+        broadcast(&mut history, 0, 1, 2);
+        read(&mut history, 10, 2, "n0", vec![1]);
+        read(&mut history, 20, 3, "n1", vec![1, 2]);
+        read(&mut history, 30, 4, "n0", vec![1, 2]);
+        read(&mut history, 40, 5, "n1", vec![1, 9]);
+        let report = check(&history);
+        assert_eq!(report.attempt_count, 2);
+        assert_eq!(report.stable_count, 1);
+        assert_eq!(report.lost, vec![2]);
+        assert_eq!(report.phantom, vec![9]);
+        assert_eq!(report.stable_latencies, vec![Duration::from_millis(11)]);
+        assert!(!report.valid());
+    }
+}
@@ -0,0 +1,361 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    time::Duration,
+};
+
+use crate::{
+    history::{History, Op, Outcome},
+    message::{MaelstromMessageBody, MessageType},
+};
+
+/// What an op did to a single register, as far as the checker is concerned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegisterOp {
+    Read(Option<usize>),
+    Write(usize),
+    Cas(usize, usize),
+}
+
+impl RegisterOp {
+    /// The register's value after this op, or `None` if it can't happen from `state`.
+    pub fn step(&self, state: Option<usize>) -> Option<Option<usize>> {
+        match *self {
+            RegisterOp::Read(value) => (value == state).then_some(state),
+            RegisterOp::Write(value) => Some(Some(value)),
+            RegisterOp::Cas(from, to) => (state == Some(from)).then_some(Some(to)),
+        }
+    }
+}
+
+/// An op on one key, ready for the search. Ops that ended in `info` have no return and
+/// are optional: they may be linearized anywhere after their invocation, or not at all.
+#[derive(Debug, Clone)]
+struct Call {
+    op: Op,
+    f: RegisterOp,
+    invoke: Duration,
+    ret: Option<Duration>,
+}
+
+/// Why a key isn't linearizable. `ops` is the history of the key cut down until every
+/// op in it is needed: drop any one and what's left can be linearized. Of those, the
+/// search got as far as `linearized`, after which none of `stuck`, read against `state`,
+/// could come next.
+#[derive(Debug, Clone)]
+pub struct Counterexample {
+    pub key: usize,
+    pub ops: Vec<Op>,
+    pub linearized: Vec<Op>,
+    pub state: Option<usize>,
+    pub stuck: Vec<Op>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LinearizabilityReport {
+    pub keys_checked: usize,
+    pub ops_checked: usize,
+    /// one for each key that failed
+    pub counterexamples: Vec<Counterexample>,
+}
+
+impl LinearizabilityReport {
+    pub fn valid(&self) -> bool {
+        self.counterexamples.is_empty()
+    }
+}
+
+/// Checks the read/write/cas ops of a lin-kv (or single register, as key 0) history.
+/// Linearizability is per key, so each key is searched on its own.
+pub fn check(history: &History) -> LinearizabilityReport {
+    let mut keys: BTreeMap<usize, Vec<Call>> = BTreeMap::new();
+    for op in history.ops() {
+        if let Some(call) = to_call(op) {
+            keys.entry(call.op.request.key.unwrap_or(0))
+                .or_default()
+                .push(call);
+        }
+    }
+    let mut report = LinearizabilityReport {
+        keys_checked: keys.len(),
+        ..LinearizabilityReport::default()
+    };
+    for (key, calls) in keys {
+        report.ops_checked += calls.len();
+        if search(key, calls.clone()).is_err() {
+            let counterexample = search(key, shrink(key, calls)).unwrap_err();
+            report.counterexamples.push(counterexample);
+        }
+    }
+    report
+}
+
+fn to_call(op: Op) -> Option<Call> {
+    let outcome = op.outcome();
+    let reply = op.reply.clone();
+    let f = match op.request.msg_type {
+        MessageType::Read => match (outcome, reply) {
+            (Outcome::Ok, Some(reply)) => {
+                RegisterOp::Read(reply.value.and_then(|x| x.as_u64()).map(|x| x as usize))
+            }
+            // a missing key reads as nothing
+            (Outcome::Fail, Some(reply))
+                if reply.code == Some(MaelstromMessageBody::KEY_DOES_NOT_EXIST) =>
+            {
+                RegisterOp::Read(None)
+            }
+            // a read that failed or whose answer never came says nothing
+            _ => return None,
+        },
+        MessageType::Write => RegisterOp::Write(op.request.value.as_ref()?.as_u64()? as usize),
+        MessageType::Cas => RegisterOp::Cas(op.request.from?, op.request.to?),
+        _ => return None,
+    };
+    let ret = match (f, outcome) {
+        (RegisterOp::Read(_), _) => op.complete,
+        (_, Outcome::Ok) => op.complete,
+        (_, Outcome::Fail) => return None,
+        (_, Outcome::Info) => None,
+    };
+    Some(Call {
+        invoke: op.invoke,
+        ret,
+        f,
+        op,
+    })
+}
+
+struct Frame {
+    linearized: Vec<u64>,
+    state: Option<usize>,
+    candidates: Vec<usize>,
+    next: usize,
+    /// the call linearized to get here
+    chosen: Option<usize>,
+}
+
+fn is_set(bits: &[u64], i: usize) -> bool {
+    bits[i / 64] & (1 << (i % 64)) != 0
+}
+
+/// Calls that could go next: everything not yet linearized that was invoked before the
+/// earliest return among required calls still outstanding.
+fn candidates(calls: &[Call], linearized: &[u64]) -> Vec<usize> {
+    let bound = (0..calls.len())
+        .filter(|i| !is_set(linearized, *i))
+        .filter_map(|i| calls[i].ret)
+        .min();
+    (0..calls.len())
+        .filter(|i| !is_set(linearized, *i))
+        .filter(|i| bound.is_none_or(|b| calls[*i].invoke < b || calls[*i].ret == Some(b)))
+        .collect()
+}
+
+/// Takes calls out one at a time for as long as what's left still fails, going round
+/// again until none can go, since taking one out can let an earlier one go too. A write
+/// stays while anything left reads or cas's from its value, or the answer would mostly
+/// be a read of a value nobody wrote.
+fn shrink(key: usize, mut calls: Vec<Call>) -> Vec<Call> {
+    calls.sort_by_key(|x| x.invoke);
+    let needs = |call: &Call, value: usize| match call.f {
+        RegisterOp::Read(x) => x == Some(value),
+        RegisterOp::Cas(from, _) => from == value,
+        RegisterOp::Write(_) => false,
+    };
+    loop {
+        let before = calls.len();
+        let mut i = 0;
+        while i < calls.len() {
+            let written = match calls[i].f {
+                RegisterOp::Write(x) | RegisterOp::Cas(_, x) => Some(x),
+                RegisterOp::Read(_) => None,
+            };
+            if written.is_some_and(|x| calls.iter().any(|y| needs(y, x))) {
+                i += 1;
+                continue;
+            }
+            let mut fewer = calls.clone();
+            fewer.remove(i);
+            if search(key, fewer.clone()).is_err() {
+                calls = fewer;
+            } else {
+                i += 1;
+            }
+        }
+        if calls.len() == before {
+            return calls;
+        }
+    }
+}
+
+/// Wing & Gong's search with Lowe's memoisation of (linearized set, state), which prunes
+/// every ordering that ends up somewhere already explored.
+fn search(key: usize, mut calls: Vec<Call>) -> Result<(), Counterexample> {
+    calls.sort_by_key(|x| x.invoke);
+    let required = calls.iter().filter(|x| x.ret.is_some()).count();
+    let words = calls.len() / 64 + 1;
+    let count = |bits: &[u64]| {
+        (0..calls.len())
+            .filter(|i| is_set(bits, *i) && calls[*i].ret.is_some())
+            .count()
+    };
+
+    let root = vec![0u64; words];
+    let mut visited: HashSet<(Vec<u64>, Option<usize>)> = HashSet::new();
+    let mut stack = vec![Frame {
+        candidates: candidates(&calls, &root),
+        linearized: root,
+        state: None,
+        next: 0,
+        chosen: None,
+    }];
+    let mut best: (usize, Vec<usize>, Vec<u64>, Option<usize>) =
+        (0, Vec::new(), stack[0].linearized.clone(), None);
+
+    while let Some(frame) = stack.last_mut() {
+        let done = count(&frame.linearized);
+        if done == required {
+            return Ok(());
+        }
+        if done > best.0 {
+            let path = stack.iter().filter_map(|x| x.chosen).collect();
+            let frame = stack.last().unwrap();
+            best = (done, path, frame.linearized.clone(), frame.state);
+            continue;
+        }
+        let Some(&i) = frame.candidates.get(frame.next) else {
+            stack.pop();
+            continue;
+        };
+        frame.next += 1;
+        let Some(state) = calls[i].f.step(frame.state) else {
+            continue;
+        };
+        let mut linearized = frame.linearized.clone();
+        linearized[i / 64] |= 1 << (i % 64);
+        if !visited.insert((linearized.clone(), state)) {
+            continue;
+        }
+        stack.push(Frame {
+            candidates: candidates(&calls, &linearized),
+            linearized,
+            state,
+            next: 0,
+            chosen: Some(i),
+        });
+    }
+
+    let (_, path, linearized, state) = best;
+    Err(Counterexample {
+        key,
+        ops: calls.iter().map(|x| x.op.clone()).collect(),
+        linearized: path.iter().map(|i| calls[*i].op.clone()).collect(),
+        state,
+        stuck: candidates(&calls, &linearized)
+            .into_iter()
+            .filter(|i| calls[*i].ret.is_some())
+            .map(|i| calls[i].op.clone())
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{message::MaelstromMessage, node_id::NodeId};
+
+    /// `ops` are (client, invoke ms, complete ms, request, reply type, read value), all
+    /// on key 0
+    fn history(ops: &[(&str, u64, u64, MessageType, usize, MessageType)]) -> History {
+        let ops: Vec<_> = ops.iter().map(|x| (0, x.clone())).collect();
+        keyed(&ops)
+    }
+
+    type Keyed<'a> = (usize, (&'a str, u64, u64, MessageType, usize, MessageType));
+
+    /// The same with each op's key in front.
+    fn keyed(ops: &[Keyed]) -> History {
+        let mut entries: Vec<(u64, MaelstromMessage)> = Vec::new();
+        for (msg_id, (key, (client, invoke, complete, f, value, reply))) in ops.iter().enumerate() {
+            let request = MaelstromMessageBody {
+                msg_id: Some(msg_id),
+                key: Some(*key),
+                value: (*f == MessageType::Write).then(|| (*value).into()),
+                ..MaelstromMessageBody::new(f.clone())
+            };
+            let response = MaelstromMessageBody {
+                in_reply_to: Some(msg_id),
+                value: (*reply == MessageType::ReadOk).then(|| (*value).into()),
+                ..MaelstromMessageBody::new(reply.clone())
+            };
+            entries.push((*invoke, msg(client, "n0", request)));
+            entries.push((*complete, msg("n0", client, response)));
+        }
+        entries.sort_by_key(|x| x.0);
+        let mut history = History::default();
+        for (time, msg) in entries {
+            history.push(Duration::from_millis(time), msg);
+        }
+        history
+    }
+
+    fn msg(src: &str, dest: &str, body: MaelstromMessageBody) -> MaelstromMessage {
+        MaelstromMessage {
+            src: src.parse().unwrap(),
+            dest: dest.parse().unwrap(),
+            body,
+        }
+    }
+
+    #[test]
+    fn concurrent_read_may_see_either_value() {
+        let report = check(&history(&[
+            ("c1", 0, 10, MessageType::Write, 1, MessageType::WriteOk),
+            ("c1", 20, 40, MessageType::Write, 2, MessageType::WriteOk),
+            ("c2", 25, 30, MessageType::Read, 2, MessageType::ReadOk),
+            ("c3", 26, 35, MessageType::Read, 1, MessageType::ReadOk),
+        ]));
+        assert!(report.valid(), "{:?}", report);
+    }
+
+    #[test]
+    fn stale_read_is_caught() {
+        let report = check(&history(&[
+            ("c1", 0, 10, MessageType::Write, 1, MessageType::WriteOk),
+            ("c1", 20, 30, MessageType::Write, 2, MessageType::WriteOk),
+            ("c2", 40, 50, MessageType::Read, 1, MessageType::ReadOk),
+        ]));
+        let counterexample = &report.counterexamples[0];
+        assert_eq!(counterexample.linearized.len(), 2);
+        assert_eq!(counterexample.state, Some(2));
+        assert_eq!(counterexample.stuck.len(), 1);
+        assert_eq!(counterexample.stuck[0].client, NodeId::Client(2));
+    }
+
+    #[test]
+    fn counterexamples_are_cut_down_for_every_failing_key() {
+        use MessageType::{Read, ReadOk, Write, WriteOk};
+        let report = check(&keyed(&[
+            (1, ("c1", 0, 10, Write, 1, WriteOk)),
+            (1, ("c2", 12, 14, Read, 1, ReadOk)),
+            (1, ("c1", 20, 30, Write, 2, WriteOk)),
+            (1, ("c2", 32, 34, Read, 2, ReadOk)),
+            (1, ("c2", 40, 50, Read, 1, ReadOk)),
+            (2, ("c3", 0, 10, Write, 3, WriteOk)),
+            (2, ("c4", 20, 30, Read, 4, ReadOk)),
+            (3, ("c5", 0, 10, Write, 5, WriteOk)),
+        ]));
+        let failed: Vec<(usize, Vec<usize>)> = report
+            .counterexamples
+            .iter()
+            .map(|x| {
+                (
+                    x.key,
+                    x.ops.iter().map(|x| x.request.msg_id.unwrap()).collect(),
+                )
+            })
+            .collect();
+        // key 1 comes down to the stale read, its write and the one after, key 2 to the
+        // read of a value never written
+        assert_eq!(failed, [(1, vec![0, 2, 4]), (2, vec![6])]);
+    }
+}
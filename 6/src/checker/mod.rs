@@ -0,0 +1,6 @@
+//! Offline checkers for run histories, standing in for Maelstrom's own so a run from the
+//! simulator can be judged inside `cargo test`.
+
+pub mod broadcast;
+pub mod linearizable;
+pub mod txn;
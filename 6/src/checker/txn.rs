@@ -0,0 +1,423 @@
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+
+use crate::{
+    config::Isolation,
+    history::{History, Op, Outcome},
+    message::MicroOp,
+    node_id::NodeId,
+};
+
+/// How one txn depends on another: `Ww` if it installed the version of a key right after
+/// the other's, `Wr` if it read the other's write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Dependency {
+    Ww,
+    Wr,
+}
+
+/// Adya's anomalies, with the txns that show each one.
+#[derive(Debug, Clone)]
+pub enum Anomaly {
+    /// dirty write: a cycle of txns overwriting each other
+    G0(Vec<Op>),
+    /// aborted read: a committed txn saw a write from one that failed
+    G1a { reader: Op, writer: Op },
+    /// intermediate read: a committed txn saw a write its writer later overwrote
+    G1b { reader: Op, writer: Op },
+    /// cyclic information flow: a cycle of write and read dependencies
+    G1c(Vec<Op>),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TxnReport {
+    pub committed_count: usize,
+    pub aborted_count: usize,
+    pub indeterminate_count: usize,
+    pub anomalies: Vec<Anomaly>,
+}
+
+impl TxnReport {
+    /// The strongest level the run held to, `None` if it didn't even manage read
+    /// uncommitted.
+    pub fn isolation(&self) -> Option<Isolation> {
+        if self.anomalies.iter().any(|x| matches!(x, Anomaly::G0(_))) {
+            None
+        } else if self.anomalies.is_empty() {
+            Some(Isolation::ReadCommitted)
+        } else {
+            Some(Isolation::ReadUncommitted)
+        }
+    }
+
+    pub fn satisfies(&self, isolation: Isolation) -> bool {
+        match (self.isolation(), isolation) {
+            (None, _) => false,
+            (Some(Isolation::ReadUncommitted), Isolation::ReadCommitted) => false,
+            (Some(_), _) => true,
+        }
+    }
+}
+
+/// Where a value written to a key came from. Like Maelstrom's txn workload, this relies on
+/// every write to a key having a unique value, so a read names exactly one writer.
+struct Write {
+    txn: usize,
+    /// the txn wrote the key again afterwards
+    intermediate: bool,
+}
+
+/// Checks the txns of a run for G0, G1a, G1b and G1c. Txns that never got an answer are
+/// counted as committed if anything read their writes, and otherwise left out. Overwrites
+/// are found from the order each key's versions went in, see [`version_order`].
+pub fn check(history: &History) -> TxnReport {
+    let ops: Vec<Op> = history
+        .ops()
+        .into_iter()
+        .filter(|x| x.request.txn.is_some())
+        .collect();
+    let mut report = TxnReport::default();
+    let mut writes: HashMap<(usize, usize), Write> = HashMap::new();
+    for (i, op) in ops.iter().enumerate() {
+        match op.outcome() {
+            Outcome::Ok => report.committed_count += 1,
+            Outcome::Fail => report.aborted_count += 1,
+            Outcome::Info => report.indeterminate_count += 1,
+        }
+        let txn = op.request.txn.as_ref().unwrap();
+        for (j, micro_op) in txn.iter().enumerate() {
+            if let MicroOp::Write(key, value) = *micro_op {
+                let intermediate = txn[j + 1..]
+                    .iter()
+                    .any(|x| matches!(x, MicroOp::Write(k, _) if *k == key));
+                writes.insert(
+                    (key, value),
+                    Write {
+                        txn: i,
+                        intermediate,
+                    },
+                );
+            }
+        }
+    }
+
+    let mut edges: BTreeSet<(usize, usize, Dependency)> = BTreeSet::new();
+    for (i, op) in ops.iter().enumerate() {
+        if op.outcome() != Outcome::Ok {
+            continue;
+        }
+        for micro_op in completed(op) {
+            let MicroOp::Read(key, value) = micro_op else {
+                continue;
+            };
+            let Some(write) = value.and_then(|x| writes.get(&(key, x))) else {
+                continue;
+            };
+            if write.txn == i {
+                continue;
+            }
+            let writer = &ops[write.txn];
+            if writer.outcome() == Outcome::Fail {
+                report.anomalies.push(Anomaly::G1a {
+                    reader: op.clone(),
+                    writer: writer.clone(),
+                });
+            } else if write.intermediate {
+                report.anomalies.push(Anomaly::G1b {
+                    reader: op.clone(),
+                    writer: writer.clone(),
+                });
+            }
+            edges.insert((write.txn, i, Dependency::Wr));
+        }
+    }
+    for (key, earlier, later) in version_order(&ops) {
+        if let (Some(from), Some(to)) = (writes.get(&(key, earlier)), writes.get(&(key, later))) {
+            if from.txn != to.txn {
+                edges.insert((from.txn, to.txn, Dependency::Ww));
+            }
+        }
+    }
+    let mut graph: Vec<Vec<(usize, Dependency)>> = vec![Vec::new(); ops.len()];
+    for (from, to, dependency) in edges {
+        graph[from].push((to, dependency));
+    }
+
+    let ww_only = |_: usize, (_, dependency): &(usize, Dependency)| *dependency == Dependency::Ww;
+    for cycle in cycles(&graph, &[Dependency::Ww], ww_only) {
+        report.anomalies.push(Anomaly::G0(
+            cycle.into_iter().map(|x| ops[x].clone()).collect(),
+        ));
+    }
+    // closed by a read that isn't also an overwrite, so no G0 cycle turns up again here
+    let read_only = |from: usize, (to, dependency): &(usize, Dependency)| {
+        *dependency == Dependency::Wr && !graph[from].contains(&(*to, Dependency::Ww))
+    };
+    for cycle in cycles(&graph, &[Dependency::Ww, Dependency::Wr], read_only) {
+        report.anomalies.push(Anomaly::G1c(
+            cycle.into_iter().map(|x| ops[x].clone()).collect(),
+        ));
+    }
+    report
+}
+
+/// The txn's ops with reads filled in from the reply, falling back to the request.
+fn completed(op: &Op) -> Vec<MicroOp> {
+    op.reply
+        .as_ref()
+        .and_then(|x| x.txn.clone())
+        .unwrap_or_else(|| op.request.txn.clone().unwrap())
+}
+
+/// Which version of each key followed which, as `(key, earlier, later)`. Like Elle's
+/// sequential keys, this takes a client's view of a key through one node to only ever
+/// move forward, which holds for nodes that only take newer versions (last-writer-wins).
+/// So any two versions a client saw in turn at a node, read or written, in one txn or
+/// across its txns, went in in that order.
+fn version_order(ops: &[Op]) -> BTreeSet<(usize, usize, usize)> {
+    let mut order = BTreeSet::new();
+    let mut seen: HashMap<(&NodeId, &NodeId), HashMap<usize, usize>> = HashMap::new();
+    for op in ops.iter().filter(|x| x.outcome() == Outcome::Ok) {
+        let session = seen.entry((&op.client, &op.node)).or_default();
+        for micro_op in completed(op) {
+            let (key, value) = match micro_op {
+                MicroOp::Read(key, Some(value)) | MicroOp::Write(key, value) => (key, value),
+                MicroOp::Read(_, None) => continue,
+            };
+            match session.insert(key, value) {
+                Some(previous) if previous != value => {
+                    order.insert((key, previous, value));
+                }
+                _ => {}
+            }
+        }
+    }
+    order
+}
+
+/// The shortest cycle through each edge `closes` picks, over edges of the given kinds.
+/// Each distinct cycle is reported once, however many of its edges close it.
+fn cycles(
+    graph: &[Vec<(usize, Dependency)>],
+    kinds: &[Dependency],
+    closes: impl Fn(usize, &(usize, Dependency)) -> bool,
+) -> Vec<Vec<usize>> {
+    let mut found: Vec<Vec<usize>> = Vec::new();
+    let mut seen: HashSet<Vec<usize>> = HashSet::new();
+    for from in 0..graph.len() {
+        for edge in graph[from].iter().filter(|x| closes(from, x)) {
+            let Some(mut cycle) = path(graph, kinds, edge.0, from) else {
+                continue;
+            };
+            // found from another edge, the same cycle starts somewhere else
+            let start = (0..cycle.len()).min_by_key(|x| cycle[*x]).unwrap();
+            cycle.rotate_left(start);
+            if seen.insert(cycle.clone()) {
+                found.push(cycle);
+            }
+        }
+    }
+    found
+}
+
+/// Shortest path from `from` to `to` over edges of the given kinds, both ends included.
+fn path(
+    graph: &[Vec<(usize, Dependency)>],
+    kinds: &[Dependency],
+    from: usize,
+    to: usize,
+) -> Option<Vec<usize>> {
+    let mut parent: HashMap<usize, usize> = HashMap::new();
+    let mut queue = VecDeque::from([from]);
+    let mut seen = HashSet::from([from]);
+    while let Some(node) = queue.pop_front() {
+        if node == to {
+            let mut path = vec![to];
+            while let Some(p) = parent.get(path.last().unwrap()) {
+                path.push(*p);
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for (next, dependency) in graph[node].iter() {
+            if kinds.contains(dependency) && seen.insert(*next) {
+                parent.insert(*next, node);
+                queue.push_back(*next);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        message::{MaelstromMessage, MaelstromMessageBody, MessageType},
+        node_id::NodeId,
+    };
+
+    /// Each txn as (request, reply), a reply of `None` being an abort, from a client of
+    /// its own.
+    fn history(txns: &[(Vec<MicroOp>, Option<Vec<MicroOp>>)]) -> History {
+        let txns: Vec<_> = txns
+            .iter()
+            .enumerate()
+            .map(|(i, (request, reply))| (i, request.clone(), reply.clone()))
+            .collect();
+        sessions(&txns)
+    }
+
+    /// A txn as (client, request, reply).
+    type Txn = (usize, Vec<MicroOp>, Option<Vec<MicroOp>>);
+
+    /// The txns run one after another.
+    fn sessions(txns: &[Txn]) -> History {
+        let mut history = History::default();
+        for (msg_id, (client, request, reply)) in txns.iter().enumerate() {
+            let client = NodeId::Client(*client);
+            let time = Duration::from_millis(msg_id as u64 * 10);
+            history.push(
+                time,
+                MaelstromMessage {
+                    src: client.clone(),
+                    dest: NodeId::Node(0),
+                    body: MaelstromMessageBody {
+                        msg_id: Some(msg_id),
+                        txn: Some(request.clone()),
+                        ..MaelstromMessageBody::new(MessageType::Txn)
+                    },
+                },
+            );
+            let body = match reply {
+                Some(txn) => MaelstromMessageBody {
+                    txn: Some(txn.clone()),
+                    ..MaelstromMessageBody::new(MessageType::TxnOk)
+                },
+                None => MaelstromMessageBody::new(MessageType::TxnOk).error(30, "aborted"),
+            };
+            history.push(
+                time + Duration::from_millis(5),
+                MaelstromMessage {
+                    src: NodeId::Node(0),
+                    dest: client,
+                    body: MaelstromMessageBody {
+                        in_reply_to: Some(msg_id),
+                        ..body
+                    },
+                },
+            );
+        }
+        history
+    }
+
+    #[test]
+    fn read_committed_run() {
+        let write = vec![MicroOp::Write(1, 1), MicroOp::Write(1, 2)];
+        let read = vec![MicroOp::Read(1, None), MicroOp::Write(1, 3)];
+        let report = check(&history(&[
+            (write.clone(), Some(write)),
+            (
+                read,
+                Some(vec![MicroOp::Read(1, Some(2)), MicroOp::Write(1, 3)]),
+            ),
+        ]));
+        assert!(report.anomalies.is_empty(), "{:?}", report);
+        assert_eq!(report.isolation(), Some(Isolation::ReadCommitted));
+    }
+
+    #[test]
+    fn finds_aborted_intermediate_and_cyclic_reads() {
+        let report = check(&history(&[
+            (vec![MicroOp::Write(1, 1)], None),
+            (
+                vec![MicroOp::Write(2, 1), MicroOp::Write(2, 2)],
+                Some(vec![MicroOp::Write(2, 1), MicroOp::Write(2, 2)]),
+            ),
+            (
+                vec![MicroOp::Read(1, None), MicroOp::Read(2, None)],
+                Some(vec![MicroOp::Read(1, Some(1)), MicroOp::Read(2, Some(1))]),
+            ),
+            (
+                vec![MicroOp::Read(3, None), MicroOp::Write(4, 1)],
+                Some(vec![MicroOp::Read(3, Some(1)), MicroOp::Write(4, 1)]),
+            ),
+            (
+                vec![MicroOp::Read(4, None), MicroOp::Write(3, 1)],
+                Some(vec![MicroOp::Read(4, Some(1)), MicroOp::Write(3, 1)]),
+            ),
+        ]));
+        let found: Vec<&str> = report
+            .anomalies
+            .iter()
+            .map(|x| match x {
+                Anomaly::G0(_) => "G0",
+                Anomaly::G1a { .. } => "G1a",
+                Anomaly::G1b { .. } => "G1b",
+                Anomaly::G1c(_) => "G1c",
+            })
+            .collect();
+        assert_eq!(found, ["G1a", "G1b", "G1c"]);
+        assert_eq!(report.isolation(), Some(Isolation::ReadUncommitted));
+        assert!(!report.satisfies(Isolation::ReadCommitted));
+    }
+
+    #[test]
+    fn finds_blind_writes_overwriting_each_other() {
+        let first = vec![
+            MicroOp::Write(1, 1),
+            MicroOp::Write(2, 1),
+            MicroOp::Write(3, 1),
+            MicroOp::Read(4, None),
+        ];
+        let second = vec![MicroOp::Write(1, 2), MicroOp::Write(2, 2)];
+        let report = check(&sessions(&[
+            (
+                0,
+                first.clone(),
+                Some(vec![
+                    MicroOp::Write(1, 1),
+                    MicroOp::Write(2, 1),
+                    MicroOp::Write(3, 1),
+                    MicroOp::Read(4, Some(1)),
+                ]),
+            ),
+            (1, second.clone(), Some(second)),
+            // client 0 sees 1 then 2 on key 1, client 1 sees 2 then 1 on key 2
+            (
+                0,
+                vec![MicroOp::Read(1, None)],
+                Some(vec![MicroOp::Read(1, Some(2))]),
+            ),
+            (
+                1,
+                vec![MicroOp::Read(2, None)],
+                Some(vec![MicroOp::Read(2, Some(1))]),
+            ),
+            // and a read cycle through the first txn as well
+            (
+                2,
+                vec![MicroOp::Read(3, None), MicroOp::Write(4, 1)],
+                Some(vec![MicroOp::Read(3, Some(1)), MicroOp::Write(4, 1)]),
+            ),
+        ]));
+        let cycles: Vec<(&str, Vec<usize>)> = report
+            .anomalies
+            .iter()
+            .filter_map(|x| match x {
+                Anomaly::G0(ops) => Some(("G0", ops)),
+                Anomaly::G1c(ops) => Some(("G1c", ops)),
+                _ => None,
+            })
+            .map(|(name, ops)| {
+                (
+                    name,
+                    ops.iter().map(|x| x.request.msg_id.unwrap()).collect(),
+                )
+            })
+            .collect();
+        assert_eq!(cycles, [("G0", vec![0, 1]), ("G1c", vec![0, 4])]);
+        assert_eq!(report.isolation(), None);
+    }
+}
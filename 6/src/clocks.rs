@@ -0,0 +1,253 @@
+use std::{cmp::Ordering, collections::BTreeMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::ClockKind, message::MaelstromMessageBody, node_id::NodeId, runtime};
+
+/// The clocks a node keeps. All of them tick locally; the ones named in the config are
+/// also stamped on everything sent to other nodes and merged from what they send back.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Clocks {
+    pub piggyback: Vec<ClockKind>,
+    pub lamport: Lamport,
+    pub vector: VectorClock,
+    pub hlc: Hlc,
+}
+
+impl Clocks {
+    pub fn new(piggyback: Vec<ClockKind>) -> Self {
+        Clocks {
+            piggyback,
+            ..Clocks::default()
+        }
+    }
+
+    /// Counts sending `body` as an event and stamps it with the clocks being piggybacked.
+    pub fn stamp(&mut self, node_id: &NodeId, body: &mut MaelstromMessageBody) {
+        for kind in self.piggyback.iter() {
+            match kind {
+                ClockKind::Lamport => body.lamport = Some(self.lamport.tick()),
+                ClockKind::Vector => {
+                    self.vector.increment(node_id);
+                    body.vclock = Some(self.vector.clone());
+                }
+                ClockKind::Hlc => body.hlc = Some(self.hlc.now()),
+            }
+        }
+    }
+
+    /// Counts receiving `body` as an event, moving past whatever clocks it carries.
+    pub fn observe(&mut self, node_id: &NodeId, body: &MaelstromMessageBody) {
+        for kind in self.piggyback.iter() {
+            match kind {
+                ClockKind::Lamport => {
+                    self.lamport.observe(body.lamport.unwrap_or_default());
+                }
+                ClockKind::Vector => {
+                    if let Some(remote) = &body.vclock {
+                        self.vector.merge(remote);
+                    }
+                    self.vector.increment(node_id);
+                }
+                ClockKind::Hlc => {
+                    self.hlc.observe(body.hlc.unwrap_or_default());
+                }
+            }
+        }
+    }
+}
+
+/// Lamport's logical clock: a counter that jumps past any time it hears of.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Lamport(pub u64);
+
+impl Lamport {
+    pub fn tick(&mut self) -> u64 {
+        self.0 += 1;
+        self.0
+    }
+
+    pub fn observe(&mut self, remote: u64) -> u64 {
+        self.0 = self.0.max(remote);
+        self.tick()
+    }
+}
+
+/// Events seen per node. Only partially ordered: one clock is before another when it's
+/// nowhere ahead of it, and neither when each is ahead somewhere (concurrent events).
+/// Nodes never mentioned are at zero, and no entry is ever stored as zero.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct VectorClock(BTreeMap<NodeId, u64>);
+
+impl VectorClock {
+    pub fn get(&self, node_id: &NodeId) -> u64 {
+        self.0.get(node_id).copied().unwrap_or_default()
+    }
+
+    pub fn increment(&mut self, node_id: &NodeId) -> u64 {
+        let entry = self.0.entry(node_id.clone()).or_default();
+        *entry += 1;
+        *entry
+    }
+
+    /// Takes the later of the two counts for every node.
+    pub fn merge(&mut self, other: &VectorClock) {
+        for (node_id, count) in other.0.iter().filter(|(_, x)| **x > 0) {
+            let entry = self.0.entry(node_id.clone()).or_default();
+            *entry = (*entry).max(*count);
+        }
+    }
+
+    pub fn concurrent(&self, other: &VectorClock) -> bool {
+        self.partial_cmp(other).is_none()
+    }
+}
+
+impl PartialOrd for VectorClock {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let (mut behind, mut ahead) = (false, false);
+        for node_id in self.0.keys().chain(other.0.keys()) {
+            match self.get(node_id).cmp(&other.get(node_id)) {
+                Ordering::Less => behind = true,
+                Ordering::Greater => ahead = true,
+                Ordering::Equal => {}
+            }
+        }
+        match (behind, ahead) {
+            (false, false) => Some(Ordering::Equal),
+            (true, false) => Some(Ordering::Less),
+            (false, true) => Some(Ordering::Greater),
+            (true, true) => None,
+        }
+    }
+}
+
+/// A hybrid logical clock reading: wall time in milliseconds, plus a counter for events
+/// in the same millisecond. Orders like the pair, and packs into a `u64` the same way.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HlcTimestamp {
+    pub wall_ms: u64,
+    pub logical: u16,
+}
+
+impl HlcTimestamp {
+    /// The top 48 bits are the wall time, good until the year 10889.
+    pub fn to_u64(self) -> u64 {
+        (self.wall_ms << 16) | self.logical as u64
+    }
+
+    /// The reading right after this one, running into the next millisecond rather than
+    /// wrapping the counter.
+    fn next(self) -> HlcTimestamp {
+        match self.logical.checked_add(1) {
+            Some(logical) => HlcTimestamp { logical, ..self },
+            None => HlcTimestamp {
+                wall_ms: self.wall_ms + 1,
+                logical: 0,
+            },
+        }
+    }
+}
+
+/// Hybrid logical clock (Kulkarni et al.): stays close to the wall clock but never goes
+/// backwards, even if the wall clock does, and every reading is distinct.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Hlc {
+    last: HlcTimestamp,
+}
+
+impl Hlc {
+    /// A reading for a local event, later than every reading before it.
+    pub fn now(&mut self) -> HlcTimestamp {
+        self.observe(self.last)
+    }
+
+    /// A reading for receiving a message stamped `remote`, later than that too.
+    pub fn observe(&mut self, remote: HlcTimestamp) -> HlcTimestamp {
+        let wall_ms = runtime::unix_time().as_millis() as u64;
+        let latest = self.last.max(remote);
+        self.last = if wall_ms > latest.wall_ms {
+            HlcTimestamp {
+                wall_ms,
+                logical: 0,
+            }
+        } else {
+            latest.next()
+        };
+        self.last
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{config::Config, sim::cluster};
+
+    #[test]
+    fn vector_clocks_order_causally_related_events_only() {
+        let (n0, n1) = (NodeId::Node(0), NodeId::Node(1));
+        let mut a = VectorClock::default();
+        a.increment(&n0);
+        let mut b = a.clone();
+        b.increment(&n1);
+        assert!(a < b);
+        a.increment(&n0);
+        assert!(a.concurrent(&b));
+        b.merge(&a);
+        assert!(a < b);
+        assert_eq!((b.get(&n0), b.get(&n1), b.get(&NodeId::Node(2))), (2, 1, 0));
+    }
+
+    #[test]
+    fn merging_in_zeros_leaves_the_clock_equal() {
+        let n0 = NodeId::Node(0);
+        let mut a = VectorClock::default();
+        a.increment(&n0);
+        let b = a.clone();
+        a.merge(&serde_json::from_str(r#"{"n1": 0}"#).unwrap());
+        assert_eq!(a.partial_cmp(&b), Some(Ordering::Equal));
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn hlc_moves_past_a_clock_running_ahead_of_ours() {
+        runtime::install_virtual(0, Duration::from_millis(1000));
+        let mut hlc = Hlc::default();
+        let first = hlc.now();
+        assert_eq!((first.wall_ms, first.logical), (1000, 0));
+        let ahead = HlcTimestamp {
+            wall_ms: 5000,
+            logical: 3,
+        };
+        assert!(hlc.observe(ahead) > ahead);
+        let later = hlc.now();
+        assert_eq!((later.wall_ms, later.logical), (5000, 5));
+        runtime::advance_to(Duration::from_secs(5));
+        assert_eq!(hlc.now().wall_ms, 6000);
+        runtime::uninstall_virtual();
+    }
+
+    #[test]
+    fn ride_along_with_broadcasts() {
+        let mut sim = cluster::start(
+            3,
+            Config {
+                clocks: vec![ClockKind::Lamport, ClockKind::Vector],
+                ..Config::default()
+            },
+        );
+        cluster::broadcast(&mut sim, &NodeId::Node(0), 1);
+        sim.run_for(Duration::from_millis(200));
+        let sender = &sim.nodes[&NodeId::Node(0)].clocks;
+        for node_id in [NodeId::Node(1), NodeId::Node(2)] {
+            let clocks = &sim.nodes[&node_id].clocks;
+            // heard about n0's send, and n0 heard back about the receipt
+            assert!(clocks.vector.get(&NodeId::Node(0)) > 0, "{}", node_id);
+            assert!(sender.vector.get(&node_id) > 0, "{}", node_id);
+            assert!(clocks.lamport.0 > 1, "{}", node_id);
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use std::env;
+
+use serde::{Deserialize, Serialize};
+
+/// Knobs that change how the node behaves, read from the environment so run.sh can set
+/// them per challenge without a rebuild.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Config {
+    pub isolation: Isolation,
+    /// Run the raft backed lin-kv, otherwise kv ops are rejected and no elections happen
+    /// (unless broadcasts go through raft).
+    pub raft: bool,
+    /// Run SWIM failure detection, so peers declared dead can be skipped.
+    pub membership: bool,
+    /// Phi above which a peer counts as suspected, with the phi-accrual failure detector
+    /// running when set.
+    pub phi_threshold: Option<f64>,
+    /// Which CRDT workload `add`/`read` are for, if any.
+    pub crdt: Option<CrdtKind>,
+    /// What ordering `broadcast` values are shown to `read` in.
+    pub broadcast: BroadcastMode,
+    /// How `generate` makes ids.
+    pub ids: IdStrategy,
+    /// How the nodes pick a leader, with no election run when unset.
+    pub election: Option<ElectionKind>,
+    /// Logical clocks stamped on messages between nodes and merged on receipt.
+    pub clocks: Vec<ClockKind>,
+    /// File to record a trace of every input and output to, for replaying later.
+    pub trace: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Isolation {
+    /// Writes hit the store as each op runs and every write is replicated.
+    ReadUncommitted,
+    /// Writes are buffered for the txn and only the final value per key is committed
+    /// and replicated, as one unit.
+    ReadCommitted,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CrdtKind {
+    GSet,
+    GCounter,
+    PNCounter,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum BroadcastMode {
+    /// Values are visible as soon as they arrive, in whatever order that is.
+    BestEffort,
+    /// Values wait until everything their origin had seen when it sent them is visible.
+    Causal,
+    /// Total order: the lowest node numbers every value and nodes show them in order.
+    Sequencer,
+    /// Total order: a token passed round the nodes says who numbers values next.
+    RotatingSequencer,
+    /// Total order: values go through the raft log and show up as they're applied.
+    Raft,
+    /// Pushed along a spanning tree, announced over the other links (Plumtree).
+    Plumtree,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum IdStrategy {
+    /// Random v4 UUIDs.
+    Uuid,
+    /// The node id, a nonce drawn at boot and a counter: `n1-5e0c0f3a9b2d4c71-2a`.
+    Counter,
+    /// Twitter's Snowflake layout: milliseconds, node index and a sequence number packed
+    /// into a `u64`, so ids sort by when they were made.
+    Snowflake,
+    /// The node's hybrid logical clock followed by the node id and boot nonce, fixed
+    /// width so the strings sort in clock order.
+    Hlc,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ElectionKind {
+    /// The highest numbered node that's up leads, found by asking upwards.
+    Bully,
+    /// Whoever holds a lease in `lin-kv`, taken and renewed with cas.
+    Lease,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ClockKind {
+    Lamport,
+    Vector,
+    Hlc,
+}
+
+impl Config {
+    pub const ISOLATION_VAR: &'static str = "GLOMERS_ISOLATION";
+    pub const RAFT_VAR: &'static str = "GLOMERS_RAFT";
+    pub const MEMBERSHIP_VAR: &'static str = "GLOMERS_MEMBERSHIP";
+    pub const PHI_THRESHOLD_VAR: &'static str = "GLOMERS_PHI_THRESHOLD";
+    pub const CRDT_VAR: &'static str = "GLOMERS_CRDT";
+    pub const BROADCAST_VAR: &'static str = "GLOMERS_BROADCAST";
+    pub const IDS_VAR: &'static str = "GLOMERS_IDS";
+    pub const ELECTION_VAR: &'static str = "GLOMERS_ELECTION";
+    /// Comma separated, e.g. `lamport,vector,hlc`.
+    pub const CLOCKS_VAR: &'static str = "GLOMERS_CLOCKS";
+    pub const TRACE_VAR: &'static str = "GLOMERS_TRACE";
+    /// Read by main rather than kept here: set, the binary replays that trace and exits.
+    pub const REPLAY_VAR: &'static str = "GLOMERS_REPLAY";
+    pub fn from_env() -> Self {
+        let isolation = match env::var(Self::ISOLATION_VAR).as_deref() {
+            Ok("read-committed") => Isolation::ReadCommitted,
+            _ => Isolation::ReadUncommitted,
+        };
+        let raft = env::var(Self::RAFT_VAR).is_ok_and(|x| x == "1");
+        let membership = env::var(Self::MEMBERSHIP_VAR).is_ok_and(|x| x == "1");
+        let phi_threshold = env::var(Self::PHI_THRESHOLD_VAR)
+            .ok()
+            .and_then(|x| x.parse().ok());
+        let crdt = match env::var(Self::CRDT_VAR).as_deref() {
+            Ok("g-set") => Some(CrdtKind::GSet),
+            Ok("g-counter") => Some(CrdtKind::GCounter),
+            Ok("pn-counter") => Some(CrdtKind::PNCounter),
+            _ => None,
+        };
+        let broadcast = match env::var(Self::BROADCAST_VAR).as_deref() {
+            Ok("causal") => BroadcastMode::Causal,
+            Ok("sequencer") => BroadcastMode::Sequencer,
+            Ok("rotating-sequencer") => BroadcastMode::RotatingSequencer,
+            Ok("raft") => BroadcastMode::Raft,
+            Ok("plumtree") => BroadcastMode::Plumtree,
+            _ => BroadcastMode::BestEffort,
+        };
+        let ids = match env::var(Self::IDS_VAR).as_deref() {
+            Ok("counter") => IdStrategy::Counter,
+            Ok("snowflake") => IdStrategy::Snowflake,
+            Ok("hlc") => IdStrategy::Hlc,
+            _ => IdStrategy::Uuid,
+        };
+        let election = match env::var(Self::ELECTION_VAR).as_deref() {
+            Ok("bully") => Some(ElectionKind::Bully),
+            Ok("lease") => Some(ElectionKind::Lease),
+            _ => None,
+        };
+        let clocks = env::var(Self::CLOCKS_VAR)
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|x| match x.trim() {
+                "lamport" => Some(ClockKind::Lamport),
+                "vector" => Some(ClockKind::Vector),
+                "hlc" => Some(ClockKind::Hlc),
+                _ => None,
+            })
+            .collect();
+        Config {
+            isolation,
+            raft,
+            membership,
+            phi_threshold,
+            crdt,
+            broadcast,
+            ids,
+            election,
+            clocks,
+            trace: env::var(Self::TRACE_VAR).ok(),
+        }
+    }
+}
+
+/// Same as running with none of the variables set.
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            isolation: Isolation::ReadUncommitted,
+            raft: false,
+            membership: false,
+            phi_threshold: None,
+            crdt: None,
+            broadcast: BroadcastMode::BestEffort,
+            ids: IdStrategy::Uuid,
+            election: None,
+            clocks: Vec::new(),
+            trace: None,
+        }
+    }
+}
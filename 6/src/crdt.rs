@@ -0,0 +1,550 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Duration,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{config::CrdtKind, runtime};
+
+/// A state-based CRDT. Mutators hand back a delta, which is itself a (small) state that can
+/// be merged anywhere a full state can, so the same `merge` handles deltas and full syncs.
+pub trait Crdt: Clone + Default + PartialEq + Serialize + DeserializeOwned {
+    fn merge(&mut self, other: &Self);
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct GCounter {
+    pub counts: BTreeMap<String, u64>,
+}
+
+impl GCounter {
+    pub fn increment(&mut self, node_id: &str, n: u64) -> Self {
+        let count = self.counts.entry(node_id.to_string()).or_insert(0);
+        *count += n;
+        GCounter {
+            counts: BTreeMap::from([(node_id.to_string(), *count)]),
+        }
+    }
+    pub fn value(&self) -> u64 {
+        self.counts.values().sum()
+    }
+}
+
+impl Crdt for GCounter {
+    fn merge(&mut self, other: &Self) {
+        for (node_id, count) in other.counts.iter() {
+            let current = self.counts.entry(node_id.clone()).or_insert(0);
+            *current = (*current).max(*count);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct PNCounter {
+    pub p: GCounter,
+    pub n: GCounter,
+}
+
+impl PNCounter {
+    pub fn add(&mut self, node_id: &str, delta: i64) -> Self {
+        let mut out = PNCounter::default();
+        if delta >= 0 {
+            out.p = self.p.increment(node_id, delta as u64);
+        } else {
+            out.n = self.n.increment(node_id, delta.unsigned_abs());
+        }
+        out
+    }
+    pub fn value(&self) -> i64 {
+        self.p.value() as i64 - self.n.value() as i64
+    }
+}
+
+impl Crdt for PNCounter {
+    fn merge(&mut self, other: &Self) {
+        self.p.merge(&other.p);
+        self.n.merge(&other.n);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(bound = "T: Serialize + DeserializeOwned + Ord")]
+pub struct GSet<T> {
+    pub elements: BTreeSet<T>,
+}
+
+impl<T> Default for GSet<T> {
+    fn default() -> Self {
+        GSet {
+            elements: BTreeSet::new(),
+        }
+    }
+}
+
+impl<T: Clone + Ord + Serialize + DeserializeOwned> GSet<T> {
+    pub fn add(&mut self, element: T) -> Self {
+        self.elements.insert(element.clone());
+        GSet {
+            elements: BTreeSet::from([element]),
+        }
+    }
+    pub fn contains(&self, element: &T) -> bool {
+        self.elements.contains(element)
+    }
+}
+
+impl<T: Clone + Ord + Serialize + DeserializeOwned> Crdt for GSet<T> {
+    fn merge(&mut self, other: &Self) {
+        self.elements.extend(other.elements.iter().cloned());
+    }
+}
+
+/// Unique tag for one add of an element to an [`ORSet`]: the adding node and its add count.
+pub type Tag = (String, u64);
+
+/// Observed-remove set: a remove only cancels the adds it has seen, so a concurrent add wins.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(bound = "T: Serialize + DeserializeOwned + Ord")]
+pub struct ORSet<T> {
+    pub adds: BTreeMap<T, BTreeSet<Tag>>,
+    pub removes: BTreeSet<Tag>,
+    /// highest tag handed out per node, merged so tags never repeat
+    pub clocks: BTreeMap<String, u64>,
+}
+
+impl<T> Default for ORSet<T> {
+    fn default() -> Self {
+        ORSet {
+            adds: BTreeMap::new(),
+            removes: BTreeSet::new(),
+            clocks: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T: Clone + Ord + Serialize + DeserializeOwned> ORSet<T> {
+    pub fn add(&mut self, node_id: &str, element: T) -> Self {
+        let clock = self.clocks.entry(node_id.to_string()).or_insert(0);
+        *clock += 1;
+        let tag = (node_id.to_string(), *clock);
+        self.adds
+            .entry(element.clone())
+            .or_default()
+            .insert(tag.clone());
+        ORSet {
+            adds: BTreeMap::from([(element, BTreeSet::from([tag]))]),
+            removes: BTreeSet::new(),
+            clocks: BTreeMap::from([(node_id.to_string(), *clock)]),
+        }
+    }
+    pub fn remove(&mut self, element: &T) -> Self {
+        let observed: BTreeSet<Tag> = self.adds.get(element).cloned().unwrap_or_default();
+        self.removes.extend(observed.iter().cloned());
+        ORSet {
+            removes: observed,
+            ..ORSet::default()
+        }
+    }
+    pub fn contains(&self, element: &T) -> bool {
+        self.adds
+            .get(element)
+            .is_some_and(|tags| tags.iter().any(|x| !self.removes.contains(x)))
+    }
+    pub fn elements(&self) -> Vec<T> {
+        self.adds
+            .keys()
+            .filter(|x| self.contains(x))
+            .cloned()
+            .collect()
+    }
+}
+
+impl<T: Clone + Ord + Serialize + DeserializeOwned> Crdt for ORSet<T> {
+    fn merge(&mut self, other: &Self) {
+        for (element, tags) in other.adds.iter() {
+            self.adds
+                .entry(element.clone())
+                .or_default()
+                .extend(tags.iter().cloned());
+        }
+        self.removes.extend(other.removes.iter().cloned());
+        for (node_id, clock) in other.clocks.iter() {
+            let current = self.clocks.entry(node_id.clone()).or_insert(0);
+            *current = (*current).max(*clock);
+        }
+    }
+}
+
+/// Last-writer-wins register, ordered on `(timestamp, node_id)` so ties still agree.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LWWRegister<T> {
+    pub value: Option<T>,
+    pub timestamp: usize,
+    pub node_id: String,
+}
+
+impl<T> Default for LWWRegister<T> {
+    fn default() -> Self {
+        LWWRegister {
+            value: None,
+            timestamp: 0,
+            node_id: String::new(),
+        }
+    }
+}
+
+impl<T: Clone + PartialEq + Serialize + DeserializeOwned> LWWRegister<T> {
+    /// Returns whether the write was newer than what was held and so took effect.
+    pub fn set(&mut self, value: T, timestamp: usize, node_id: &str) -> bool {
+        if (timestamp, node_id) < (self.timestamp, self.node_id.as_str()) {
+            return false;
+        }
+        self.value = Some(value);
+        self.timestamp = timestamp;
+        self.node_id = node_id.to_string();
+        true
+    }
+    pub fn get(&self) -> Option<&T> {
+        self.value.as_ref()
+    }
+}
+
+impl<T: Clone + PartialEq + Serialize + DeserializeOwned> Crdt for LWWRegister<T> {
+    fn merge(&mut self, other: &Self) {
+        if let Some(value) = &other.value {
+            self.set(value.clone(), other.timestamp, &other.node_id);
+        }
+    }
+}
+
+/// Replicates any [`Crdt`] by gossip: local deltas are batched and sent every
+/// `GOSSIP_MS`, with the full state sent every `FULL_STATE_EVERY` rounds instead so
+/// anything lost to a partition is caught up once it heals.
+pub struct Gossip<T: Crdt> {
+    pub state: T,
+    pub delta: T,
+    pub rounds: usize,
+    pub last_gossip: Duration,
+}
+
+impl<T: Crdt> Gossip<T> {
+    pub const GOSSIP_MS: u128 = 100;
+    pub const FULL_STATE_EVERY: usize = 10;
+
+    pub fn new() -> Self {
+        Gossip {
+            state: T::default(),
+            delta: T::default(),
+            rounds: 0,
+            last_gossip: runtime::now(),
+        }
+    }
+    /// Local change, the delta from one of the state's mutators.
+    pub fn apply(&mut self, delta: T) {
+        self.state.merge(&delta);
+        self.delta.merge(&delta);
+    }
+    /// Remote state or delta from a peer.
+    pub fn merge(&mut self, remote: &T) {
+        self.state.merge(remote);
+    }
+    /// What to send to every peer this tick, if it's time to gossip.
+    pub fn tick(&mut self) -> Option<T> {
+        if (runtime::now() - self.last_gossip).as_millis() < Self::GOSSIP_MS {
+            return None;
+        }
+        self.last_gossip = runtime::now();
+        self.rounds += 1;
+        let delta = std::mem::take(&mut self.delta);
+        if self.rounds.is_multiple_of(Self::FULL_STATE_EVERY) {
+            Some(self.state.clone())
+        } else if delta != T::default() {
+            Some(delta)
+        } else {
+            None
+        }
+    }
+}
+
+impl<T: Crdt> Default for Gossip<T> {
+    fn default() -> Self {
+        Gossip::new()
+    }
+}
+
+/// The CRDT backing whichever of the g-set, g-counter or pn-counter workloads is running.
+pub enum CrdtNode {
+    GSet(Gossip<GSet<usize>>),
+    GCounter(Gossip<GCounter>),
+    PNCounter(Gossip<PNCounter>),
+}
+
+impl CrdtNode {
+    pub fn new(kind: CrdtKind) -> Self {
+        match kind {
+            CrdtKind::GSet => CrdtNode::GSet(Gossip::new()),
+            CrdtKind::GCounter => CrdtNode::GCounter(Gossip::new()),
+            CrdtKind::PNCounter => CrdtNode::PNCounter(Gossip::new()),
+        }
+    }
+    /// `element` is only used by g-set and `delta` only by the counters. An add missing the
+    /// one it needs, or shrinking a g-counter, is turned away without touching the state.
+    pub fn add(
+        &mut self,
+        node_id: &str,
+        element: Option<usize>,
+        delta: Option<i64>,
+    ) -> Result<(), &'static str> {
+        match self {
+            CrdtNode::GSet(g) => {
+                let element = element.ok_or("g-set adds need an element")?;
+                let delta = g.state.add(element);
+                g.apply(delta);
+            }
+            CrdtNode::GCounter(g) => {
+                let delta = delta
+                    .and_then(|x| u64::try_from(x).ok())
+                    .ok_or("g-counter adds need a non-negative delta")?;
+                let delta = g.state.increment(node_id, delta);
+                g.apply(delta);
+            }
+            CrdtNode::PNCounter(g) => {
+                let delta = delta.ok_or("pn-counter adds need a delta")?;
+                let delta = g.state.add(node_id, delta);
+                g.apply(delta);
+            }
+        }
+        Ok(())
+    }
+    pub fn read(&self) -> Value {
+        match self {
+            CrdtNode::GSet(g) => serde_json::to_value(&g.state.elements).unwrap(),
+            CrdtNode::GCounter(g) => g.state.value().into(),
+            CrdtNode::PNCounter(g) => g.state.value().into(),
+        }
+    }
+    pub fn merge(&mut self, remote: Value) -> Result<(), serde_json::Error> {
+        match self {
+            CrdtNode::GSet(g) => g.merge(&serde_json::from_value(remote)?),
+            CrdtNode::GCounter(g) => g.merge(&serde_json::from_value(remote)?),
+            CrdtNode::PNCounter(g) => g.merge(&serde_json::from_value(remote)?),
+        }
+        Ok(())
+    }
+    pub fn tick(&mut self) -> Option<Value> {
+        match self {
+            CrdtNode::GSet(g) => g.tick().map(|x| serde_json::to_value(x).unwrap()),
+            CrdtNode::GCounter(g) => g.tick().map(|x| serde_json::to_value(x).unwrap()),
+            CrdtNode::PNCounter(g) => g.tick().map(|x| serde_json::to_value(x).unwrap()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        message::{MaelstromMessageBody, MessageType},
+        sim::cluster,
+    };
+    use std::{fmt::Debug, time::Duration};
+
+    /// Merging is commutative, associative and idempotent for these replicas.
+    fn merge_laws<T: Crdt + Debug>(a: &T, b: &T, c: &T) {
+        let merged = |x: &T, y: &T| {
+            let mut out = x.clone();
+            out.merge(y);
+            out
+        };
+        assert_eq!(merged(a, b), merged(b, a));
+        assert_eq!(merged(&merged(a, b), c), merged(a, &merged(b, c)));
+        assert_eq!(merged(a, a), *a);
+        assert_eq!(merged(&merged(a, b), b), merged(a, b));
+    }
+
+    /// The delta `op` hands back, sent as JSON and merged into a replica from before the
+    /// op, brings it to the same state as the replica that made it.
+    fn delta_round_trip<T: Crdt + Debug>(mut replica: T, op: impl FnOnce(&mut T) -> T) {
+        let mut behind = replica.clone();
+        let delta = op(&mut replica);
+        let sent: T = serde_json::from_value(serde_json::to_value(&delta).unwrap()).unwrap();
+        assert_eq!(sent, delta);
+        behind.merge(&sent);
+        assert_eq!(behind, replica);
+    }
+
+    #[test]
+    fn g_counters_merge_and_ship_deltas() {
+        let (mut a, mut b, mut c) = (
+            GCounter::default(),
+            GCounter::default(),
+            GCounter::default(),
+        );
+        a.increment("n0", 2);
+        b.increment("n1", 3);
+        b.increment("n0", 1);
+        c.increment("n2", 5);
+        merge_laws(&a, &b, &c);
+        delta_round_trip(a, |x| x.increment("n0", 4));
+    }
+
+    #[test]
+    fn pn_counters_merge_and_ship_deltas() {
+        let (mut a, mut b, mut c) = (
+            PNCounter::default(),
+            PNCounter::default(),
+            PNCounter::default(),
+        );
+        a.add("n0", 2);
+        b.add("n1", -3);
+        c.add("n0", -1);
+        c.add("n2", 4);
+        merge_laws(&a, &b, &c);
+        let mut all = a.clone();
+        all.merge(&b);
+        all.merge(&c);
+        assert_eq!(all.value(), 2);
+        delta_round_trip(all.clone(), |x| x.add("n1", -7));
+        delta_round_trip(all, |x| x.add("n1", 7));
+    }
+
+    #[test]
+    fn g_sets_merge_and_ship_deltas() {
+        let (mut a, mut b, mut c) = (GSet::default(), GSet::default(), GSet::default());
+        a.add(1);
+        b.add(1);
+        b.add(2);
+        c.add(3);
+        merge_laws(&a, &b, &c);
+        delta_round_trip(a, |x| x.add(4));
+    }
+
+    #[test]
+    fn or_sets_let_a_concurrent_add_beat_a_remove() {
+        let mut a = ORSet::default();
+        a.add("n0", 1);
+        let mut b = a.clone();
+        // b removes the add it has seen while a adds 1 again
+        let tombstone = b.remove(&1);
+        let readd = a.add("n0", 1);
+        assert!(!b.contains(&1));
+        a.merge(&tombstone);
+        b.merge(&readd);
+        assert!(a.contains(&1) && b.contains(&1));
+        assert_eq!(a, b);
+        // the tombstone keeps a stale copy of the first add from bringing it back
+        let mut c = ORSet::default();
+        c.add("n1", 2);
+        let stale = c.clone();
+        c.remove(&2);
+        c.merge(&stale);
+        assert!(!c.contains(&2));
+        merge_laws(&a, &b, &c);
+        delta_round_trip(a.clone(), |x| x.add("n1", 3));
+        delta_round_trip(a, |x| x.remove(&1));
+    }
+
+    #[test]
+    fn lww_registers_break_timestamp_ties_on_node_id() {
+        let (mut a, mut b, mut c) = (
+            LWWRegister::default(),
+            LWWRegister::default(),
+            LWWRegister::default(),
+        );
+        a.set(1, 5, "n0");
+        b.set(2, 5, "n1");
+        c.set(3, 4, "n2");
+        merge_laws(&a, &b, &c);
+        for (mut x, y) in [(a.clone(), &b), (b.clone(), &a)] {
+            x.merge(y);
+            assert_eq!(x.get(), Some(&2));
+        }
+        assert!(!b.set(9, 5, "n0"));
+        delta_round_trip(c, |x| {
+            x.set(6, 7, "n2");
+            x.clone()
+        });
+    }
+
+    #[test]
+    fn g_counter_converges() {
+        let mut sim = cluster::start(
+            3,
+            Config {
+                crdt: Some(CrdtKind::GCounter),
+                ..Config::default()
+            },
+        );
+        for (i, node_id) in sim.node_ids().iter().enumerate() {
+            sim.request(
+                node_id,
+                MaelstromMessageBody {
+                    delta: Some(i as i64 + 1),
+                    ..MaelstromMessageBody::new(MessageType::Add)
+                },
+            );
+        }
+        sim.run_for(Duration::from_millis(300));
+        for read in cluster::read_all(&mut sim) {
+            assert_eq!(read.value, Some(6.into()));
+        }
+    }
+
+    #[test]
+    fn g_counter_turns_away_negative_and_missing_deltas() {
+        let mut sim = cluster::start(
+            1,
+            Config {
+                crdt: Some(CrdtKind::GCounter),
+                ..Config::default()
+            },
+        );
+        let n0 = sim.node_ids()[0].clone();
+        for delta in [Some(-1), None] {
+            let reply = sim
+                .call(
+                    &n0,
+                    MaelstromMessageBody {
+                        delta,
+                        ..MaelstromMessageBody::new(MessageType::Add)
+                    },
+                    cluster::TIMEOUT,
+                )
+                .unwrap();
+            assert_eq!(
+                reply.body.code,
+                Some(MaelstromMessageBody::MALFORMED_REQUEST)
+            );
+        }
+        // a stray text field is no reason to turn one away
+        let reply = sim
+            .call(
+                &n0,
+                MaelstromMessageBody {
+                    delta: Some(2),
+                    text: Some(String::from("hi")),
+                    ..MaelstromMessageBody::new(MessageType::Add)
+                },
+                cluster::TIMEOUT,
+            )
+            .unwrap();
+        assert_eq!(reply.body.msg_type, MessageType::AddOk);
+        assert_eq!(cluster::read_all(&mut sim)[0].value, Some(2.into()));
+    }
+
+    #[test]
+    fn adds_without_a_crdt_are_not_supported() {
+        let mut sim = cluster::start(1, Config::default());
+        let add = MaelstromMessageBody {
+            delta: Some(1),
+            ..MaelstromMessageBody::new(MessageType::Add)
+        };
+        let reply = sim
+            .call(&sim.node_ids()[0].clone(), add, cluster::TIMEOUT)
+            .unwrap();
+        assert_eq!(reply.body.code, Some(MaelstromMessageBody::NOT_SUPPORTED));
+    }
+}
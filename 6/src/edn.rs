@@ -0,0 +1,220 @@
+use serde_json::{Map, Number, Value};
+
+/// Reads EDN, the Clojure data format Jepsen (and so Maelstrom) writes its histories in,
+/// into JSON values: keywords and symbols become their name as a string (`:ok` is
+/// `"ok"`), `nil` is `null`, maps have their keys printed to strings, and lists, vectors
+/// and sets are all arrays. Tags like `#jepsen.history.Op` are dropped in favour of the
+/// form they tag. Enough for a history, not a general reader.
+pub fn parse(text: &str) -> Result<Vec<Value>, String> {
+    let mut reader = Reader {
+        chars: text.chars().collect(),
+        pos: 0,
+    };
+    let mut forms = Vec::new();
+    while let Some(form) = reader.next()? {
+        forms.push(form);
+    }
+    Ok(forms)
+}
+
+struct Reader {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Reader {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_blank(&mut self) {
+        while let Some(c) = self.peek() {
+            match c {
+                ';' => {
+                    while self.peek().is_some_and(|x| x != '\n') {
+                        self.pos += 1;
+                    }
+                }
+                c if c.is_whitespace() || c == ',' => self.pos += 1,
+                _ => return,
+            }
+        }
+    }
+
+    /// The next form, or `None` at the end of the input.
+    fn next(&mut self) -> Result<Option<Value>, String> {
+        self.skip_blank();
+        let Some(c) = self.peek() else {
+            return Ok(None);
+        };
+        let value = match c {
+            '[' | '(' => {
+                self.pos += 1;
+                Value::Array(self.seq(if c == '[' { ']' } else { ')' })?)
+            }
+            '{' => {
+                self.pos += 1;
+                self.map()?
+            }
+            '"' => {
+                self.pos += 1;
+                Value::String(self.string()?)
+            }
+            '#' => {
+                self.pos += 1;
+                match self.peek() {
+                    Some('{') => {
+                        self.pos += 1;
+                        Value::Array(self.seq('}')?)
+                    }
+                    Some('_') => {
+                        self.pos += 1;
+                        self.form()?;
+                        return self.next();
+                    }
+                    _ => {
+                        self.token();
+                        return self.next();
+                    }
+                }
+            }
+            ']' | ')' | '}' => return Err(format!("unexpected {} at {}", c, self.pos)),
+            _ => {
+                let token = self.token();
+                Reader::atom(&token)
+            }
+        };
+        Ok(Some(value))
+    }
+
+    fn form(&mut self) -> Result<Value, String> {
+        self.next()?
+            .ok_or_else(|| String::from("unexpected end of input"))
+    }
+
+    fn seq(&mut self, close: char) -> Result<Vec<Value>, String> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_blank();
+            match self.peek() {
+                Some(c) if c == close => {
+                    self.pos += 1;
+                    return Ok(items);
+                }
+                Some(_) => items.push(self.form()?),
+                None => return Err(format!("missing {}", close)),
+            }
+        }
+    }
+
+    fn map(&mut self) -> Result<Value, String> {
+        let items = self.seq('}')?;
+        if items.len() % 2 != 0 {
+            return Err(String::from("map with a key but no value"));
+        }
+        let mut map = Map::new();
+        for pair in items.chunks(2) {
+            let key = match &pair[0] {
+                Value::String(x) => x.clone(),
+                x => x.to_string(),
+            };
+            map.insert(key, pair[1].clone());
+        }
+        Ok(Value::Object(map))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let mut s = String::new();
+        loop {
+            let c = self.peek().ok_or("unterminated string")?;
+            self.pos += 1;
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let escaped = self.peek().ok_or("unterminated string")?;
+                    self.pos += 1;
+                    s.push(match escaped {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        x => x,
+                    });
+                }
+                c => s.push(c),
+            }
+        }
+    }
+
+    /// Everything up to the next delimiter: a number, keyword, symbol or character.
+    fn token(&mut self) -> String {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || ",;\"()[]{}".contains(c) {
+                break;
+            }
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn atom(token: &str) -> Value {
+        match token {
+            "nil" => return Value::Null,
+            "true" => return Value::Bool(true),
+            "false" => return Value::Bool(false),
+            _ => {}
+        }
+        let digits = token.trim_end_matches(['N', 'M']);
+        if let Ok(x) = digits.parse::<i64>() {
+            return Value::Number(x.into());
+        }
+        if let Some(x) = digits.parse::<f64>().ok().and_then(Number::from_f64) {
+            return Value::Number(x);
+        }
+        let name = token
+            .strip_prefix(':')
+            .or_else(|| token.strip_prefix('\\'))
+            .unwrap_or(token);
+        Value::String(name.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn reads_a_jepsen_op() {
+        let text = r#"
+            ; one op per line
+            {:type :ok, :f :txn, :value [[:r 1 nil] [:w 1 2]], :time 1500000, :process 3,
+             :error [:key-does-not-exist "no \"1\""], :seen #{2}}
+            #jepsen.history.Op{:index 1, :nemesis #_ignored nil}
+        "#;
+        let forms = parse(text).unwrap();
+        assert_eq!(
+            forms,
+            [
+                json!({
+                    "type": "ok",
+                    "f": "txn",
+                    "value": [["r", 1, null], ["w", 1, 2]],
+                    "time": 1500000,
+                    "process": 3,
+                    "error": ["key-does-not-exist", "no \"1\""],
+                    "seen": [2],
+                }),
+                json!({"index": 1, "nemesis": null}),
+            ]
+        );
+    }
+
+    #[test]
+    fn complains_about_unbalanced_input() {
+        assert!(parse("{:type :ok").is_err());
+        assert!(parse("[1 2}").is_err());
+        assert!(parse("{:type}").is_err());
+    }
+}
@@ -0,0 +1,650 @@
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    config::ElectionKind,
+    history::micros,
+    message::{MaelstromMessage, MaelstromMessageBody, MessageType, MsgIds},
+    node_id::NodeId,
+    runtime,
+};
+
+/// Who leads, as far as this node knows, and the fencing token that came with it.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Leadership {
+    pub leader: Option<NodeId>,
+    pub token: u64,
+}
+
+/// The lease as kept in `lin-kv`, packed into one number so it can be cas'd.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lease {
+    pub token: u64,
+    /// index of the node holding it
+    pub holder: usize,
+    /// bumped on every renewal, so the others can see the holder is still there
+    pub renewals: u64,
+}
+
+impl Lease {
+    /// holder in the low 10 bits, renewals in the next 30, the token above that
+    fn pack(&self) -> usize {
+        ((self.token << 40) | ((self.renewals & 0x3fff_ffff) << 10) | self.holder as u64) as usize
+    }
+
+    fn unpack(value: usize) -> Self {
+        let value = value as u64;
+        Lease {
+            token: value >> 40,
+            holder: (value & 0x3ff) as usize,
+            renewals: (value >> 10) & 0x3fff_ffff,
+        }
+    }
+}
+
+/// A read or cas out to `lin-kv`, waiting on its reply.
+#[derive(Serialize, Debug, Clone)]
+pub struct KvRequest {
+    pub msg_id: usize,
+    #[serde(with = "micros")]
+    pub sent_at: Duration,
+    /// what a cas puts in place of the current lease
+    pub to: Option<Lease>,
+}
+
+/// Picks a single leader among the nodes. Every change of leader (including there being
+/// none for a while) is queued on `changes` for whoever wants to know, and every
+/// leadership comes with a fencing token that only goes up, so whatever a deposed leader
+/// sends late can be told apart and turned away.
+///
+/// Bully (Garcia-Molina): a node that finds itself without a leader asks every node above
+/// it to take over, and if none answers in time it announces itself to everyone. The
+/// announcement is repeated as a heartbeat; missing it, or the failure detectors giving up
+/// on the leader, starts another election. It's only as good as the failure detection: a
+/// partition gets a leader on each side, and the tokens are what say which is newer.
+///
+/// Lease: the leader is whoever holds the lease in `lin-kv`, taken and renewed with cas.
+/// The rest only take over once they have seen it go unchanged for a whole lease by their
+/// own clock, and the holder counts its lease from when it sent the cas, so clocks don't
+/// have to agree, only run at about the same rate.
+#[derive(Serialize, Debug, Clone)]
+pub struct Election {
+    pub kind: ElectionKind,
+    pub node_id: NodeId,
+    pub node_ids: Vec<NodeId>,
+    pub leader: Option<NodeId>,
+    /// the current leadership's fencing token
+    pub token: u64,
+    /// leadership changes not yet taken
+    pub changes: Vec<Leadership>,
+    /// the highest token heard of, which a new leader has to go past
+    max_token: u64,
+    /// when the leader next announces itself, or the lease is next read or renewed
+    #[serde(with = "micros")]
+    next_round: Duration,
+    /// bully: when to give up waiting on an answer, or on the announcement after one
+    electing_until: Option<Duration>,
+    answered: bool,
+    #[serde(with = "micros")]
+    last_heard: Duration,
+    /// lease: the last one seen in `lin-kv`, and when it was first seen
+    pub lease: Option<Lease>,
+    #[serde(with = "micros")]
+    lease_seen_at: Duration,
+    /// when our own lease runs out, unless renewed
+    held_until: Option<Duration>,
+    pub request: Option<KvRequest>,
+    #[serde(skip)]
+    msg_ids: MsgIds,
+}
+
+impl Election {
+    /// How long to wait for a node above to answer an `election`.
+    pub const ANSWER_TIMEOUT_MS: u64 = 50;
+    /// How long to wait for the announcement once one has.
+    pub const COORDINATOR_TIMEOUT_MS: u64 = 150;
+    pub const HEARTBEAT_MS: u64 = 100;
+    pub const LEADER_TIMEOUT_MS: u64 = 300;
+    pub const LEASE_MS: u64 = 500;
+    pub const RENEW_MS: u64 = 100;
+    /// the holder stops leading this long before anyone else could take the lease over
+    pub const GUARD_MS: u64 = 50;
+    /// the lease is the only thing the nodes keep in `lin-kv`
+    pub const LEASE_KEY: usize = 0;
+
+    pub fn new(
+        kind: ElectionKind,
+        node_id: NodeId,
+        node_ids: Vec<NodeId>,
+        msg_ids: MsgIds,
+    ) -> Self {
+        let now = runtime::now();
+        Election {
+            kind,
+            node_id,
+            node_ids,
+            leader: None,
+            token: 0,
+            changes: Vec::new(),
+            max_token: 0,
+            next_round: now,
+            electing_until: None,
+            answered: false,
+            last_heard: now,
+            lease: None,
+            lease_seen_at: now,
+            held_until: None,
+            request: None,
+            msg_ids,
+        }
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.leader.as_ref() == Some(&self.node_id)
+    }
+
+    pub fn take_changes(&mut self) -> Vec<Leadership> {
+        std::mem::take(&mut self.changes)
+    }
+
+    /// `unreachable` are the peers the failure detectors have given up on, which bully
+    /// doesn't wait on.
+    pub fn tick(&mut self, unreachable: &[NodeId]) -> Vec<MaelstromMessage> {
+        match self.kind {
+            ElectionKind::Bully => self.bully_tick(unreachable),
+            ElectionKind::Lease => self.lease_tick(),
+        }
+    }
+
+    fn bully_tick(&mut self, unreachable: &[NodeId]) -> Vec<MaelstromMessage> {
+        let now = runtime::now();
+        if self.is_leader() {
+            if now < self.next_round {
+                return Vec::new();
+            }
+            self.next_round = now + Duration::from_millis(Election::HEARTBEAT_MS);
+            return self.announce();
+        }
+        if let Some(until) = self.electing_until {
+            if now < until {
+                return Vec::new();
+            }
+            self.electing_until = None;
+            // someone above answered but never took over: ask again
+            return match self.answered {
+                true => self.start_election(unreachable),
+                false => self.take_over(),
+            };
+        }
+        let leader_gone = self.leader.as_ref().is_none_or(|x| {
+            unreachable.contains(x)
+                || now >= self.last_heard + Duration::from_millis(Election::LEADER_TIMEOUT_MS)
+        });
+        match leader_gone {
+            true => self.start_election(unreachable),
+            false => Vec::new(),
+        }
+    }
+
+    fn start_election(&mut self, unreachable: &[NodeId]) -> Vec<MaelstromMessage> {
+        self.set_leader(None, self.token);
+        let higher: Vec<NodeId> = self
+            .node_ids
+            .iter()
+            .filter(|x| **x > self.node_id && !unreachable.contains(x))
+            .cloned()
+            .collect();
+        if higher.is_empty() {
+            return self.take_over();
+        }
+        self.electing_until =
+            Some(runtime::now() + Duration::from_millis(Election::ANSWER_TIMEOUT_MS));
+        self.answered = false;
+        higher.iter().map(|x| self.election(x)).collect()
+    }
+
+    fn take_over(&mut self) -> Vec<MaelstromMessage> {
+        self.max_token = self.next_token();
+        self.electing_until = None;
+        self.set_leader(Some(self.node_id.clone()), self.max_token);
+        self.next_round = runtime::now() + Duration::from_millis(Election::HEARTBEAT_MS);
+        self.announce()
+    }
+
+    /// The first token past any heard of that is ours alone. Tokens are dealt out by
+    /// position among the nodes, `n` apart, so both sides of a partition taking over from
+    /// the same last leader still end up with different ones.
+    fn next_token(&self) -> u64 {
+        let n = self.node_ids.len().max(1) as u64;
+        let position = self.node_ids.iter().position(|x| *x == self.node_id);
+        (self.max_token / n + 1) * n + position.unwrap_or_default() as u64
+    }
+
+    /// A node below has lost its leader. It gets an `election_ok` back either way; here
+    /// we take over from it, or just tell it who leads if that's us.
+    pub fn handle_election(
+        &mut self,
+        msg: &MaelstromMessage,
+        unreachable: &[NodeId],
+    ) -> Vec<MaelstromMessage> {
+        let token = msg.body.token.unwrap_or_default();
+        self.max_token = self.max_token.max(token);
+        if self.is_leader() && token <= self.token {
+            return vec![self.coordinator(&msg.src)];
+        }
+        match self.electing_until {
+            Some(_) => Vec::new(),
+            None => self.start_election(unreachable),
+        }
+    }
+
+    pub fn handle_election_ok(&mut self) {
+        if self.electing_until.is_some() {
+            self.answered = true;
+            self.electing_until =
+                Some(runtime::now() + Duration::from_millis(Election::COORDINATOR_TIMEOUT_MS));
+        }
+    }
+
+    /// A node announcing it leads. One with a token older than ours is told the newer
+    /// token, which makes it stand down and run again; one below us gets bullied.
+    pub fn handle_coordinator(
+        &mut self,
+        msg: &MaelstromMessage,
+        unreachable: &[NodeId],
+    ) -> Vec<MaelstromMessage> {
+        let token = msg.body.token.unwrap_or_default();
+        self.max_token = self.max_token.max(token);
+        if token < self.token {
+            return vec![self.election(&msg.src)];
+        }
+        if msg.src < self.node_id {
+            return match (self.is_leader(), self.electing_until) {
+                // with a token past theirs
+                (true, _) => self.take_over(),
+                (false, Some(_)) => Vec::new(),
+                (false, None) => self.start_election(unreachable),
+            };
+        }
+        self.electing_until = None;
+        self.last_heard = runtime::now();
+        self.set_leader(Some(msg.src.clone()), token);
+        Vec::new()
+    }
+
+    fn lease_tick(&mut self) -> Vec<MaelstromMessage> {
+        let now = runtime::now();
+        if self.held_until.is_some_and(|x| now >= x) {
+            self.held_until = None;
+            self.set_leader(None, self.token);
+        }
+        // a lost request or reply is given up on after a round
+        let round = Duration::from_millis(Election::RENEW_MS);
+        if self
+            .request
+            .as_ref()
+            .is_some_and(|x| now < x.sent_at + round)
+        {
+            return Vec::new();
+        }
+        self.request = None;
+        if now < self.next_round {
+            return Vec::new();
+        }
+        self.next_round = now + round;
+        match self.lease.filter(|_| self.held_until.is_some()) {
+            Some(lease) => {
+                let renewed = Lease {
+                    renewals: lease.renewals + 1,
+                    ..lease
+                };
+                vec![self.cas(Some(lease), renewed)]
+            }
+            None => vec![self.read()],
+        }
+    }
+
+    /// A reply from `lin-kv` to the request in flight; anything else is a late reply to
+    /// one already given up on.
+    pub fn handle_kv_reply(&mut self, msg: &MaelstromMessage) -> Vec<MaelstromMessage> {
+        if self.request.as_ref().map(|x| x.msg_id) != msg.body.in_reply_to {
+            return Vec::new();
+        }
+        let request = self.request.take().unwrap();
+        match (&msg.body.msg_type, msg.body.code) {
+            (MessageType::ReadOk, _) => {
+                let Some(value) = msg.body.value.as_ref().and_then(Value::as_u64) else {
+                    return Vec::new();
+                };
+                let lease = Lease::unpack(value as usize);
+                self.observe(lease);
+                if !self.lease_expired() {
+                    return Vec::new();
+                }
+                let ours = Lease {
+                    token: lease.token + 1,
+                    holder: self.node_id.index().unwrap_or_default(),
+                    renewals: 0,
+                };
+                vec![self.cas(Some(lease), ours)]
+            }
+            (MessageType::CasOk, _) => {
+                let lease = request.to.unwrap();
+                self.lease = Some(lease);
+                self.lease_seen_at = runtime::now();
+                self.held_until = Some(
+                    request.sent_at
+                        + Duration::from_millis(Election::LEASE_MS - Election::GUARD_MS),
+                );
+                self.set_leader(Some(self.node_id.clone()), lease.token);
+                Vec::new()
+            }
+            // nobody has ever held it
+            (MessageType::Error, Some(MaelstromMessageBody::KEY_DOES_NOT_EXIST)) => {
+                let ours = Lease {
+                    token: 1,
+                    holder: self.node_id.index().unwrap_or_default(),
+                    renewals: 0,
+                };
+                vec![self.cas(None, ours)]
+            }
+            // someone else got there first, and if the lease was ours it isn't any more
+            (MessageType::Error, Some(MaelstromMessageBody::PRECONDITION_FAILED)) => {
+                if self.held_until.take().is_some() {
+                    self.set_leader(None, self.token);
+                }
+                self.next_round = runtime::now();
+                Vec::new()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Takes in a lease read from `lin-kv`. A lease still in our name that we aren't
+    /// renewing (from before a restart, say) is waited out like anyone else's.
+    fn observe(&mut self, lease: Lease) {
+        if self.lease != Some(lease) {
+            self.lease = Some(lease);
+            self.lease_seen_at = runtime::now();
+        }
+        let holder = NodeId::Node(lease.holder);
+        if holder == self.node_id && self.held_until.is_some() {
+            return;
+        }
+        self.held_until = None;
+        let leader = Some(holder).filter(|x| *x != self.node_id && !self.lease_expired());
+        self.set_leader(leader, lease.token);
+    }
+
+    fn lease_expired(&self) -> bool {
+        runtime::now() >= self.lease_seen_at + Duration::from_millis(Election::LEASE_MS)
+    }
+
+    fn set_leader(&mut self, leader: Option<NodeId>, token: u64) {
+        self.max_token = self.max_token.max(token);
+        if self.leader == leader && self.token == token {
+            return;
+        }
+        self.leader = leader.clone();
+        self.token = token;
+        self.changes.push(Leadership { leader, token });
+    }
+
+    fn announce(&self) -> Vec<MaelstromMessage> {
+        self.node_ids
+            .iter()
+            .filter(|x| **x != self.node_id)
+            .map(|x| self.coordinator(x))
+            .collect()
+    }
+
+    fn coordinator(&self, dest: &NodeId) -> MaelstromMessage {
+        let body = MaelstromMessageBody {
+            token: Some(self.token),
+            ..MaelstromMessageBody::new(MessageType::Coordinator)
+        };
+        self.message(dest, body)
+    }
+
+    fn election(&mut self, dest: &NodeId) -> MaelstromMessage {
+        let body = MaelstromMessageBody {
+            msg_id: Some(self.msg_id()),
+            token: Some(self.max_token),
+            ..MaelstromMessageBody::new(MessageType::Election)
+        };
+        self.message(dest, body)
+    }
+
+    fn read(&mut self) -> MaelstromMessage {
+        let msg_id = self.msg_id();
+        self.request = Some(KvRequest {
+            msg_id,
+            sent_at: runtime::now(),
+            to: None,
+        });
+        let body = MaelstromMessageBody {
+            msg_id: Some(msg_id),
+            key: Some(Election::LEASE_KEY),
+            ..MaelstromMessageBody::new(MessageType::Read)
+        };
+        self.message(&NodeId::lin_kv(), body)
+    }
+
+    /// Swaps `from` for `to`, or puts `to` in place if there's no lease yet.
+    fn cas(&mut self, from: Option<Lease>, to: Lease) -> MaelstromMessage {
+        let msg_id = self.msg_id();
+        self.request = Some(KvRequest {
+            msg_id,
+            sent_at: runtime::now(),
+            to: Some(to),
+        });
+        let body = MaelstromMessageBody {
+            msg_id: Some(msg_id),
+            key: Some(Election::LEASE_KEY),
+            from: Some(from.map(|x| x.pack()).unwrap_or_default()),
+            to: Some(to.pack()),
+            create_if_not_exists: from.is_none().then_some(true),
+            ..MaelstromMessageBody::new(MessageType::Cas)
+        };
+        self.message(&NodeId::lin_kv(), body)
+    }
+
+    fn msg_id(&mut self) -> usize {
+        self.msg_ids.next()
+    }
+
+    fn message(&self, dest: &NodeId, body: MaelstromMessageBody) -> MaelstromMessage {
+        MaelstromMessage {
+            src: self.node_id.clone(),
+            dest: dest.clone(),
+            body,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        nemesis::Fault,
+        sim::{cluster, Simulator},
+    };
+
+    fn reply(request: &MaelstromMessage, body: MaelstromMessageBody) -> MaelstromMessage {
+        MaelstromMessage {
+            src: request.dest.clone(),
+            dest: request.src.clone(),
+            body: MaelstromMessageBody {
+                in_reply_to: request.body.msg_id,
+                ..body
+            },
+        }
+    }
+
+    #[test]
+    fn bully_carries_its_token_apart_from_the_raft_term() {
+        runtime::install_virtual(0, Duration::ZERO);
+        let (n0, n1) = (NodeId::Node(0), NodeId::Node(1));
+        let msg_ids = MsgIds::default();
+        msg_ids.next();
+        let mut election = Election::new(
+            ElectionKind::Bully,
+            n0.clone(),
+            vec![n0, n1.clone()],
+            msg_ids.clone(),
+        );
+        let ask = election.tick(&[]).remove(0);
+        assert_eq!(ask.body.msg_type, MessageType::Election);
+        assert_eq!((ask.body.token, ask.body.term), (Some(0), None));
+        assert_eq!(ask.body.msg_id, Some(2));
+        let coordinator = MaelstromMessage {
+            src: n1.clone(),
+            dest: ask.src.clone(),
+            body: MaelstromMessageBody {
+                token: Some(5),
+                ..MaelstromMessageBody::new(MessageType::Coordinator)
+            },
+        };
+        election.handle_coordinator(&coordinator, &[]);
+        assert_eq!(
+            election.take_changes().pop(),
+            Some(Leadership {
+                leader: Some(n1),
+                token: 5
+            })
+        );
+        runtime::uninstall_virtual();
+    }
+
+    #[test]
+    fn both_sides_of_a_partition_take_over_with_different_tokens() {
+        runtime::install_virtual(0, Duration::ZERO);
+        let node_ids: Vec<NodeId> = (0..3).map(NodeId::Node).collect();
+        let (n0, n1, n2) = (NodeId::Node(0), NodeId::Node(1), NodeId::Node(2));
+        // n0 lost its leader with token 4 and asks both; n2 is cut off from the other two
+        let ask = |dest: &NodeId| MaelstromMessage {
+            src: n0.clone(),
+            dest: dest.clone(),
+            body: MaelstromMessageBody {
+                token: Some(4),
+                ..MaelstromMessageBody::new(MessageType::Election)
+            },
+        };
+        let mut tokens = Vec::new();
+        for (node_id, unreachable) in [(n1.clone(), vec![n2.clone()]), (n2.clone(), vec![n1])] {
+            let mut election = Election::new(
+                ElectionKind::Bully,
+                node_id.clone(),
+                node_ids.clone(),
+                MsgIds::default(),
+            );
+            election.handle_election(&ask(&node_id), &unreachable);
+            assert_eq!(election.leader, Some(node_id));
+            tokens.push(election.token);
+        }
+        assert_eq!(tokens, [7, 8]);
+        runtime::uninstall_virtual();
+    }
+
+    #[test]
+    fn takes_an_expired_lease_with_a_higher_token() {
+        runtime::install_virtual(0, Duration::ZERO);
+        let (n0, n1) = (NodeId::Node(0), NodeId::Node(1));
+        let mut election = Election::new(
+            ElectionKind::Lease,
+            n0.clone(),
+            vec![n0, n1.clone()],
+            MsgIds::default(),
+        );
+        let theirs = Lease {
+            token: 3,
+            holder: 1,
+            renewals: 7,
+        };
+        assert_eq!(Lease::unpack(theirs.pack()), theirs);
+
+        let read = election.tick(&[]).remove(0);
+        assert_eq!(read.body.msg_type, MessageType::Read);
+        let value = MaelstromMessageBody {
+            value: Some(theirs.pack().into()),
+            ..MaelstromMessageBody::new(MessageType::ReadOk)
+        };
+        assert!(election
+            .handle_kv_reply(&reply(&read, value.clone()))
+            .is_empty());
+        assert_eq!(election.take_changes()[0].leader, Some(n1));
+
+        // unchanged for a whole lease: it's ours to take
+        runtime::advance_to(Duration::from_millis(Election::LEASE_MS));
+        let read = election.tick(&[]).remove(0);
+        let cas = election.handle_kv_reply(&reply(&read, value)).remove(0);
+        assert_eq!(cas.body.from, Some(theirs.pack()));
+        assert_eq!(Lease::unpack(cas.body.to.unwrap()).token, 4);
+        let cas_ok = MaelstromMessageBody::new(MessageType::CasOk);
+        election.handle_kv_reply(&reply(&cas, cas_ok));
+        assert!(election.is_leader());
+        assert_eq!(election.token, 4);
+
+        // a renewal that fails means someone else has it now
+        runtime::advance_to(Duration::from_millis(
+            Election::LEASE_MS + Election::RENEW_MS,
+        ));
+        let renew = election.tick(&[]).remove(0);
+        assert_eq!(Lease::unpack(renew.body.to.unwrap()).renewals, 1);
+        let failed = renew
+            .body
+            .error(MaelstromMessageBody::PRECONDITION_FAILED, "lease moved on");
+        election.handle_kv_reply(&reply(&renew, failed));
+        assert!(!election.is_leader());
+        runtime::uninstall_virtual();
+    }
+
+    /// Everyone agrees on a leader, the rest agree on another with a later token while it
+    /// is down, and everyone agrees again once it's back. Returns the three leaders.
+    fn leadership_survives_a_crashed_leader_in(kind: ElectionKind) -> Vec<NodeId> {
+        let mut sim = cluster::start(
+            4,
+            Config {
+                election: Some(kind),
+                ..Config::default()
+            },
+        );
+        let second = Duration::from_secs(1);
+        let agreed = |sim: &Simulator, node_ids: &[NodeId]| {
+            let views: Vec<(Option<NodeId>, u64)> = node_ids
+                .iter()
+                .map(|x| sim.nodes[x].election.as_ref().unwrap())
+                .map(|x| (x.leader.clone(), x.token))
+                .collect();
+            assert!(views.iter().all(|x| *x == views[0]), "{:?}", views);
+            (views[0].0.clone().unwrap(), views[0].1)
+        };
+        sim.run_for(second);
+        let (first, token) = agreed(&sim, &sim.node_ids());
+        sim.nemesis
+            .add(second, second * 2, Fault::Crash(Some(first.clone())));
+        sim.run_for(second * 3 / 2);
+        let rest: Vec<NodeId> = sim.node_ids().into_iter().filter(|x| *x != first).collect();
+        let (second_leader, later) = agreed(&sim, &rest);
+        assert_ne!(second_leader, first);
+        assert!(later > token);
+        sim.run_for(second * 2);
+        let (third, latest) = agreed(&sim, &sim.node_ids());
+        assert!(latest >= later);
+        vec![first, second_leader, third]
+    }
+
+    #[test]
+    fn bully_elects_the_highest_live_node() {
+        let leaders = leadership_survives_a_crashed_leader_in(ElectionKind::Bully);
+        assert_eq!(leaders, [NodeId::Node(3), NodeId::Node(2), NodeId::Node(3)]);
+    }
+
+    #[test]
+    fn lease_leadership_survives_a_crashed_holder() {
+        leadership_survives_a_crashed_leader_in(ElectionKind::Lease);
+    }
+}
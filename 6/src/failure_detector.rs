@@ -0,0 +1,197 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::Duration,
+};
+
+use crate::{node_id::NodeId, runtime};
+
+/// Recent gaps between messages from one peer.
+#[derive(Debug, Clone)]
+pub struct ArrivalWindow {
+    pub last: Duration,
+    /// in milliseconds, oldest first
+    pub intervals: VecDeque<f64>,
+}
+
+impl ArrivalWindow {
+    fn mean(&self) -> f64 {
+        self.intervals.iter().sum::<f64>() / self.intervals.len() as f64
+    }
+
+    fn std_dev(&self) -> f64 {
+        let mean = self.mean();
+        let variance = self
+            .intervals
+            .iter()
+            .map(|x| (x - mean).powi(2))
+            .sum::<f64>()
+            / self.intervals.len() as f64;
+        variance.sqrt()
+    }
+}
+
+/// Phi-accrual failure detector (Hayashibara et al.), one window per peer. Rather than a
+/// yes/no, it says how unlikely the silence since a peer's last message is given the
+/// gaps seen so far: phi of 1 is a 10% chance the peer is fine, 2 is 1%, and so on. A
+/// peer is suspected once phi passes the threshold.
+///
+/// Any message counts as a sign of life. Links that go quiet are kept warm with a
+/// `heartbeat`, so idle peers don't look dead.
+#[derive(Debug, Clone)]
+pub struct PhiAccrual {
+    pub threshold: f64,
+    pub peers: BTreeMap<NodeId, ArrivalWindow>,
+    /// when we last sent each peer anything
+    pub last_sent: BTreeMap<NodeId, Duration>,
+}
+
+impl PhiAccrual {
+    pub const WINDOW: usize = 100;
+    pub const HEARTBEAT_MS: u64 = 100;
+    /// keeps a perfectly regular peer from being suspected the moment it's a little late
+    pub const MIN_STD_DEV_MS: f64 = 20.0;
+    /// how many times longer to wait before retransmitting to a suspected peer
+    pub const BACKOFF: u128 = 10;
+    /// silence after which a suspected peer is given up on until it's heard from again
+    pub const DEAD_AFTER_MS: u64 = 1000;
+
+    pub fn new(threshold: f64, peers: &[NodeId]) -> Self {
+        let now = runtime::now();
+        PhiAccrual {
+            threshold,
+            peers: BTreeMap::new(),
+            last_sent: peers.iter().map(|x| (x.clone(), now)).collect(),
+        }
+    }
+
+    /// Notes a message from `peer`.
+    pub fn heartbeat(&mut self, peer: &NodeId) {
+        let now = runtime::now();
+        let Some(window) = self.peers.get_mut(peer) else {
+            self.peers.insert(
+                peer.clone(),
+                ArrivalWindow {
+                    last: now,
+                    intervals: VecDeque::new(),
+                },
+            );
+            return;
+        };
+        window
+            .intervals
+            .push_back((now - window.last).as_secs_f64() * 1000.0);
+        if window.intervals.len() > PhiAccrual::WINDOW {
+            window.intervals.pop_front();
+        }
+        window.last = now;
+    }
+
+    pub fn sent(&mut self, peer: &NodeId) {
+        if let Some(last) = self.last_sent.get_mut(peer) {
+            *last = runtime::now();
+        }
+    }
+
+    /// How suspicious the current silence from `peer` is. Zero until there's been a gap
+    /// to go on.
+    pub fn phi(&self, peer: &NodeId) -> f64 {
+        let Some(window) = self.peers.get(peer).filter(|x| !x.intervals.is_empty()) else {
+            return 0.0;
+        };
+        let silence = (runtime::now() - window.last).as_secs_f64() * 1000.0;
+        let (mean, std_dev) = (
+            window.mean(),
+            window.std_dev().max(PhiAccrual::MIN_STD_DEV_MS),
+        );
+        // logistic approximation of the normal CDF, as Akka and Cassandra use
+        let y = (silence - mean) / std_dev;
+        let e = (-y * (1.5976 + 0.070566 * y * y)).exp();
+        if silence > mean {
+            -(e / (1.0 + e)).log10()
+        } else {
+            -(1.0 - 1.0 / (1.0 + e)).log10()
+        }
+    }
+
+    pub fn is_suspected(&self, peer: &NodeId) -> bool {
+        self.phi(peer) > self.threshold
+    }
+
+    /// Suspected and silent for at least [`PhiAccrual::DEAD_AFTER_MS`], so not worth
+    /// retransmitting to at all.
+    pub fn is_dead(&self, peer: &NodeId) -> bool {
+        let dead_after = Duration::from_millis(PhiAccrual::DEAD_AFTER_MS);
+        self.is_suspected(peer)
+            && self
+                .peers
+                .get(peer)
+                .is_some_and(|x| runtime::now() - x.last >= dead_after)
+    }
+
+    /// Peers we haven't sent anything to for a heartbeat interval.
+    pub fn quiet_peers(&self) -> Vec<NodeId> {
+        let now = runtime::now();
+        self.last_sent
+            .iter()
+            .filter(|(_, at)| now >= **at + Duration::from_millis(PhiAccrual::HEARTBEAT_MS))
+            .map(|(peer, _)| peer.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, nemesis::Fault, sim::cluster};
+
+    #[test]
+    fn phi_grows_with_silence() {
+        runtime::install_virtual(0, Duration::ZERO);
+        let n1 = NodeId::Node(1);
+        let mut detector = PhiAccrual::new(8.0, std::slice::from_ref(&n1));
+        assert_eq!(detector.phi(&n1), 0.0);
+        for i in 0..20 {
+            runtime::advance_to(Duration::from_millis(100 * i));
+            detector.heartbeat(&n1);
+        }
+        let phi_after = |ms: u64| {
+            runtime::advance_to(Duration::from_millis(1900 + ms));
+            detector.phi(&n1)
+        };
+        let (on_time, late, gone) = (phi_after(100), phi_after(200), phi_after(1000));
+        assert!(on_time < 1.0, "{}", on_time);
+        assert!(on_time < late && late < gone, "{} {}", late, gone);
+        assert!(detector.is_suspected(&n1));
+        runtime::uninstall_virtual();
+    }
+
+    #[test]
+    fn retransmissions_back_off_from_a_suspected_peer() {
+        let mut sim = cluster::start(
+            3,
+            Config {
+                phi_threshold: Some(8.0),
+                ..Config::default()
+            },
+        );
+        let second = Duration::from_secs(1);
+        let n2 = NodeId::Node(2);
+        sim.nemesis
+            .add(second, second * 2, Fault::Isolate(Some(n2.clone())));
+        sim.run_for(second + second / 2);
+        cluster::broadcast(&mut sim, &NodeId::Node(0), 1);
+        sim.run_for(second + second / 3);
+        let n0 = &sim.nodes[&NodeId::Node(0)];
+        assert!(n0.is_suspected(&n2));
+        assert!(!n0.is_suspected(&NodeId::Node(1)));
+        // every 30ms would be over 40 by now
+        assert!(n0.metrics.retransmissions < 10, "{:?}", n0.metrics);
+        // and after a second of silence it stops trying until n2 is back
+        assert!(!n0.is_reachable(&n2));
+        assert!(n0.awaiting_ack.iter().all(|x| x.msg.dest != n2));
+        assert_eq!(n0.parked[&n2].len(), 1);
+        sim.run_for(second);
+        assert!(!sim.nodes[&NodeId::Node(0)].is_suspected(&n2));
+        assert_eq!(sim.nodes[&n2].delivery_order, [1]);
+    }
+}
@@ -0,0 +1,382 @@
+use std::{collections::HashMap, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    edn,
+    message::{MaelstromMessage, MaelstromMessageBody, MessageType},
+    node_id::NodeId,
+};
+
+/// One client-facing message and when it was seen. Requests go client -> node,
+/// replies node -> client.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    #[serde(with = "micros")]
+    pub time: Duration,
+    pub msg: MaelstromMessage,
+}
+
+/// Everything the clients of a run sent and got back, in the order it happened.
+/// The simulator fills one in as it goes, or one can be read from JSON lines of [`Entry`],
+/// or from the `history.edn` of a Maelstrom run.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct History {
+    pub entries: Vec<Entry>,
+}
+
+/// How an operation ended, as Maelstrom sees it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Ok,
+    /// definitely didn't happen
+    Fail,
+    /// might or might not have happened: no reply, or an indefinite error
+    Info,
+}
+
+/// A client request paired with its reply, if it got one.
+#[derive(Debug, Clone)]
+pub struct Op {
+    pub client: NodeId,
+    pub node: NodeId,
+    pub invoke: Duration,
+    pub complete: Option<Duration>,
+    pub request: MaelstromMessageBody,
+    pub reply: Option<MaelstromMessageBody>,
+}
+
+impl Op {
+    pub fn outcome(&self) -> Outcome {
+        match &self.reply {
+            None => Outcome::Info,
+            Some(reply) if reply.msg_type == MessageType::Error => {
+                if History::is_definite(reply.code.unwrap_or(0)) {
+                    Outcome::Fail
+                } else {
+                    Outcome::Info
+                }
+            }
+            Some(_) => Outcome::Ok,
+        }
+    }
+}
+
+impl History {
+    /// Maelstrom's timeout (0) and crash (13) leave it unknown whether the op took effect.
+    pub fn is_definite(code: usize) -> bool {
+        !matches!(code, 0 | 13)
+    }
+
+    pub fn push(&mut self, time: Duration, msg: MaelstromMessage) {
+        self.entries.push(Entry { time, msg });
+    }
+
+    pub fn from_json_lines(lines: &str) -> Result<Self, serde_json::Error> {
+        let entries = lines
+            .lines()
+            .filter(|x| !x.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<Entry>, _>>()?;
+        Ok(History { entries })
+    }
+
+    pub fn to_json_lines(&self) -> String {
+        self.entries
+            .iter()
+            .map(|x| serde_json::to_string(x).unwrap() + "\n")
+            .collect()
+    }
+
+    /// Reads the `history.edn` Maelstrom leaves in `store/<workload>/<run>/`, turning each
+    /// op back into the request a client sent and the reply it got, so the checkers can go
+    /// over a real run. The history doesn't say which node served a process, so like Jepsen
+    /// this takes process `p` to have talked to node `p mod nodes`; its client is `c<p>`.
+    /// Ops that ended in `:info` never got a reply, and nemesis ops are skipped.
+    pub fn from_jepsen_edn(text: &str, nodes: usize) -> Result<Self, String> {
+        let mut history = History::default();
+        // the request each process is waiting on
+        let mut invoked: HashMap<usize, MaelstromMessageBody> = HashMap::new();
+        let forms = edn::parse(text)?.into_iter().flat_map(|x| match x {
+            // a whole history as one vector
+            Value::Array(ops) => ops,
+            op => vec![op],
+        });
+        for (msg_id, op) in forms.enumerate() {
+            let Some(process) = op["process"].as_u64().map(|x| x as usize) else {
+                continue;
+            };
+            let time = Duration::from_nanos(op["time"].as_u64().unwrap_or(0));
+            let client = NodeId::Client(process);
+            let node = NodeId::Node(process % nodes);
+            let body = match op["type"].as_str() {
+                Some("invoke") => {
+                    let request = MaelstromMessageBody {
+                        msg_id: Some(msg_id),
+                        ..History::edn_request(&op)?
+                    };
+                    invoked.insert(process, request.clone());
+                    let (src, dest) = (client, node);
+                    history.push(
+                        time,
+                        MaelstromMessage {
+                            src,
+                            dest,
+                            body: request,
+                        },
+                    );
+                    continue;
+                }
+                Some(_) if !invoked.contains_key(&process) => {
+                    return Err(format!(
+                        "process {} completed an op it never invoked",
+                        process
+                    ));
+                }
+                Some("ok") => History::edn_reply(&invoked[&process], &op)?,
+                Some("fail") => invoked[&process].error(History::edn_error(&op), "failed"),
+                _ => continue,
+            };
+            invoked.remove(&process);
+            let (src, dest) = (node, client);
+            history.push(time, MaelstromMessage { src, dest, body });
+        }
+        Ok(history)
+    }
+
+    fn edn_request(op: &Value) -> Result<MaelstromMessageBody, String> {
+        let value = op["value"].clone();
+        let parse = |e: serde_json::Error| format!("{}: {}", e, op);
+        let new = MaelstromMessageBody::new;
+        let body = match (op["f"].as_str().unwrap_or_default(), &value) {
+            ("broadcast", _) => MaelstromMessageBody {
+                message: serde_json::from_value(value).map_err(parse)?,
+                ..new(MessageType::Broadcast)
+            },
+            // lin-kv reads name a key, broadcast and counter reads don't
+            ("read", Value::Array(_)) => {
+                let (key, _): (usize, Value) = serde_json::from_value(value).map_err(parse)?;
+                MaelstromMessageBody {
+                    key: Some(key),
+                    ..new(MessageType::Read)
+                }
+            }
+            ("read", _) => new(MessageType::Read),
+            ("write", _) => {
+                let (key, value): (usize, Value) = serde_json::from_value(value).map_err(parse)?;
+                MaelstromMessageBody {
+                    key: Some(key),
+                    value: Some(value),
+                    ..new(MessageType::Write)
+                }
+            }
+            ("cas", _) => {
+                let (key, (from, to)): (usize, (usize, usize)) =
+                    serde_json::from_value(value).map_err(parse)?;
+                MaelstromMessageBody {
+                    key: Some(key),
+                    from: Some(from),
+                    to: Some(to),
+                    ..new(MessageType::Cas)
+                }
+            }
+            ("add", _) => MaelstromMessageBody {
+                delta: serde_json::from_value(value).map_err(parse)?,
+                ..new(MessageType::Add)
+            },
+            ("txn", _) => MaelstromMessageBody {
+                txn: serde_json::from_value(value).map_err(parse)?,
+                ..new(MessageType::Txn)
+            },
+            (f, _) => return Err(format!("no request for :{} ops: {}", f, op)),
+        };
+        Ok(body)
+    }
+
+    fn edn_reply(
+        request: &MaelstromMessageBody,
+        op: &Value,
+    ) -> Result<MaelstromMessageBody, String> {
+        let value = op["value"].clone();
+        let parse = |e: serde_json::Error| format!("{}: {}", e, op);
+        let body = match request.msg_type {
+            MessageType::Read if request.key.is_some() => {
+                let (_, value): (usize, Value) = serde_json::from_value(value).map_err(parse)?;
+                MaelstromMessageBody {
+                    value: Some(value),
+                    ..request.reply(MessageType::ReadOk)
+                }
+            }
+            MessageType::Read => match value {
+                Value::Array(_) => MaelstromMessageBody {
+                    messages: serde_json::from_value(value).map_err(parse)?,
+                    ..request.reply(MessageType::ReadOk)
+                },
+                value => MaelstromMessageBody {
+                    value: Some(value),
+                    ..request.reply(MessageType::ReadOk)
+                },
+            },
+            MessageType::Txn => MaelstromMessageBody {
+                txn: serde_json::from_value(value).map_err(parse)?,
+                ..request.reply(MessageType::TxnOk)
+            },
+            MessageType::Broadcast => request.reply(MessageType::BroadcastOk),
+            MessageType::Write => request.reply(MessageType::WriteOk),
+            MessageType::Cas => request.reply(MessageType::CasOk),
+            _ => request.reply(MessageType::AddOk),
+        };
+        Ok(body)
+    }
+
+    /// The Maelstrom error code behind a failed op's `:error`, which is a code, a name, or a
+    /// `[name text]` pair depending on the workload.
+    fn edn_error(op: &Value) -> usize {
+        let error = match &op["error"] {
+            Value::Array(x) => x.first().cloned().unwrap_or_default(),
+            x => x.clone(),
+        };
+        if let Some(code) = error.as_u64() {
+            return code as usize;
+        }
+        match error.as_str().unwrap_or_default() {
+            "timeout" | "net-timeout" => MaelstromMessageBody::TIMEOUT,
+            "not-supported" => MaelstromMessageBody::NOT_SUPPORTED,
+            "temporarily-unavailable" => MaelstromMessageBody::TEMPORARILY_UNAVAILABLE,
+            "malformed-request" => MaelstromMessageBody::MALFORMED_REQUEST,
+            "crash" => 13,
+            "key-does-not-exist" | "not-found" => MaelstromMessageBody::KEY_DOES_NOT_EXIST,
+            "precondition-failed" => MaelstromMessageBody::PRECONDITION_FAILED,
+            // the op failed for sure, we just don't have a name for why: abort
+            _ => 14,
+        }
+    }
+
+    /// Pairs every client request with the reply to it, in invocation order.
+    pub fn ops(&self) -> Vec<Op> {
+        let mut ops: Vec<Op> = Vec::new();
+        for entry in self.entries.iter() {
+            let msg = &entry.msg;
+            if msg.src.is_client() {
+                ops.push(Op {
+                    client: msg.src.clone(),
+                    node: msg.dest.clone(),
+                    invoke: entry.time,
+                    complete: None,
+                    request: msg.body.clone(),
+                    reply: None,
+                });
+            } else if msg.dest.is_client() {
+                let op = ops.iter_mut().find(|x| {
+                    x.reply.is_none()
+                        && x.client == msg.dest
+                        && x.request.msg_id.is_some()
+                        && x.request.msg_id == msg.body.in_reply_to
+                });
+                if let Some(op) = op {
+                    op.complete = Some(entry.time);
+                    op.reply = Some(msg.body.clone());
+                }
+            }
+        }
+        ops
+    }
+}
+
+/// Durations as whole microseconds, for the JSON line formats.
+pub(crate) mod micros {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(time: &Duration, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u64(time.as_micros() as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        Ok(Duration::from_micros(u64::deserialize(d)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker::{broadcast, linearizable};
+
+    #[test]
+    fn reads_a_maelstrom_lin_kv_history() {
+        let text = r#"
+            {:type :invoke, :f :write, :value [0 1], :time 1000, :process 0, :index 0}
+            {:type :invoke, :f :read, :value [0 nil], :time 2000, :process 1, :index 1}
+            {:type :ok, :f :write, :value [0 1], :time 3000, :process 0, :index 2}
+            {:type :ok, :f :read, :value [0 1], :time 4000, :process 1, :index 3}
+            {:type :info, :f :start-partition, :value nil, :time 4500, :process :nemesis}
+            {:type :invoke, :f :cas, :value [0 [3 4]], :time 5000, :process 1, :index 4}
+            {:type :fail, :f :cas, :value [0 [3 4]], :time 6000, :process 1, :index 5,
+             :error [:precondition-failed "expected 3, had 1"]}
+            {:type :invoke, :f :write, :value [0 2], :time 7000, :process 2, :index 6}
+            {:type :info, :f :write, :value [0 2], :time 8000, :process 2, :index 7,
+             :error :net-timeout}
+        "#;
+        let history = History::from_jepsen_edn(text, 2).unwrap();
+        let ops = history.ops();
+        let summary: Vec<(NodeId, NodeId, MessageType, Outcome)> = ops
+            .iter()
+            .map(|x| {
+                let (client, node) = (x.client.clone(), x.node.clone());
+                (client, node, x.request.msg_type.clone(), x.outcome())
+            })
+            .collect();
+        let expected = [
+            (
+                NodeId::Client(0),
+                NodeId::Node(0),
+                MessageType::Write,
+                Outcome::Ok,
+            ),
+            (
+                NodeId::Client(1),
+                NodeId::Node(1),
+                MessageType::Read,
+                Outcome::Ok,
+            ),
+            (
+                NodeId::Client(1),
+                NodeId::Node(1),
+                MessageType::Cas,
+                Outcome::Fail,
+            ),
+            (
+                NodeId::Client(2),
+                NodeId::Node(0),
+                MessageType::Write,
+                Outcome::Info,
+            ),
+        ];
+        assert_eq!(summary, expected);
+        assert_eq!(ops[0].complete, Some(Duration::from_micros(3)));
+        assert_eq!(ops[2].request.from, Some(3));
+        assert!(linearizable::check(&history).valid());
+    }
+
+    #[test]
+    fn reads_a_maelstrom_broadcast_history() {
+        let text = r#"
+            [{:type :invoke, :f :broadcast, :value 7, :time 1000, :process 0}
+             {:type :ok, :f :broadcast, :value 7, :time 2000, :process 0}
+             {:type :invoke, :f :read, :value nil, :time 3000, :process 1}
+             {:type :ok, :f :read, :value [7 8], :time 4000, :process 1}]
+        "#;
+        let history = History::from_jepsen_edn(text, 2).unwrap();
+        let report = broadcast::check(&history);
+        assert_eq!(report.acknowledged_count, 1);
+        assert_eq!(report.phantom, [8]);
+    }
+
+    #[test]
+    fn refuses_a_completion_nobody_invoked() {
+        let text = "{:type :ok, :f :read, :value nil, :time 1000, :process 0}";
+        assert!(History::from_jepsen_edn(text, 1).is_err());
+    }
+}
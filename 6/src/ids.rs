@@ -0,0 +1,162 @@
+use std::time::Duration;
+
+use crate::{clocks::Hlc, config::IdStrategy, node_id::NodeId, runtime};
+
+/// Makes the ids `generate` hands out. Apart from `Uuid` none of the strategies need
+/// randomness to stay unique while a node runs: ids are unique per node by construction
+/// and across nodes by the node id in them. Across restarts, none of them hands anything
+/// out until the millisecond the node booted in is over, so a restart always boots in a
+/// later one than any id the last run made. `Counter` and `Hlc` ids carry that boot
+/// millisecond, and `Snowflake` never goes back to it.
+#[derive(Debug, Clone)]
+pub struct IdGenerator {
+    strategy: IdStrategy,
+    /// milliseconds since [`IdGenerator::SNOWFLAKE_EPOCH_MS`] when the node started
+    boot_ms: u64,
+    counter: u64,
+    /// Snowflake's last millisecond and the sequence number within it
+    last_ms: u64,
+    sequence: u64,
+}
+
+impl IdGenerator {
+    /// 2024-01-01T00:00:00Z, what Snowflake timestamps and boot milliseconds count from.
+    pub const SNOWFLAKE_EPOCH_MS: u64 = 1_704_067_200_000;
+    const NODE_BITS: u32 = 10;
+    const SEQUENCE_BITS: u32 = 12;
+
+    pub fn new(strategy: IdStrategy) -> Self {
+        let boot_ms = epoch_ms();
+        IdGenerator {
+            strategy,
+            boot_ms,
+            counter: 0,
+            // as though the boot millisecond's sequence numbers were all used up
+            last_ms: boot_ms,
+            sequence: (1 << Self::SEQUENCE_BITS) - 1,
+        }
+    }
+
+    /// The next id, after waiting out the boot millisecond if it isn't over yet.
+    pub fn next(&mut self, node_id: &NodeId, hlc: &mut Hlc) -> String {
+        if epoch_ms() <= self.boot_ms {
+            wait_past(self.boot_ms);
+        }
+        match self.strategy {
+            IdStrategy::Uuid => runtime::uuid().to_string(),
+            IdStrategy::Counter => {
+                self.counter += 1;
+                format!("{}-{:x}-{:x}", node_id, self.boot_ms, self.counter)
+            }
+            IdStrategy::Snowflake => self.snowflake(node_id).to_string(),
+            // fixed width so they sort as made, good until the year 2527
+            IdStrategy::Hlc => {
+                format!("{:015x}-{}-{:x}", hlc.now().to_u64(), node_id, self.boot_ms)
+            }
+        }
+    }
+
+    /// 41 bits of milliseconds since [`Self::SNOWFLAKE_EPOCH_MS`], 10 of node index and 12
+    /// of sequence. Past 4096 ids in a millisecond it waits for the next one, and a wall
+    /// clock that steps back is ignored, so ids only ever go up. Never running ahead of
+    /// the wall clock is what lets a restarted node start after its boot millisecond and
+    /// know the last run can't have used anything from there on.
+    fn snowflake(&mut self, node_id: &NodeId) -> u64 {
+        let mut ms = epoch_ms();
+        if ms <= self.last_ms && (self.sequence + 1) >> Self::SEQUENCE_BITS != 0 {
+            ms = wait_past(self.last_ms);
+        }
+        if ms > self.last_ms {
+            self.last_ms = ms;
+            self.sequence = 0;
+        } else {
+            self.sequence += 1;
+        }
+        let node = node_id.index().unwrap_or_default() as u64 & ((1 << Self::NODE_BITS) - 1);
+        (self.last_ms << (Self::NODE_BITS + Self::SEQUENCE_BITS))
+            | (node << Self::SEQUENCE_BITS)
+            | self.sequence
+    }
+}
+
+fn epoch_ms() -> u64 {
+    (runtime::unix_time().as_millis() as u64).saturating_sub(IdGenerator::SNOWFLAKE_EPOCH_MS)
+}
+
+/// Waits for the first millisecond after `ms`, returning it.
+fn wait_past(ms: u64) -> u64 {
+    runtime::wait_until_unix(Duration::from_millis(
+        IdGenerator::SNOWFLAKE_EPOCH_MS + ms + 1,
+    ));
+    epoch_ms()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    /// Ids from one run of three nodes, booted at `boot` and asked for `per_ms` ids in
+    /// each of `at`.
+    fn run(strategy: IdStrategy, per_ms: usize, boot: u64, at: &[u64]) -> Vec<String> {
+        runtime::advance_to(Duration::from_millis(boot));
+        let mut nodes: Vec<(IdGenerator, Hlc)> = (0..3)
+            .map(|_| (IdGenerator::new(strategy), Hlc::default()))
+            .collect();
+        let mut ids = Vec::new();
+        for ms in at {
+            runtime::advance_to(Duration::from_millis(*ms));
+            for (i, (generator, hlc)) in nodes.iter_mut().enumerate() {
+                ids.extend((0..per_ms).map(|_| generator.next(&NodeId::Node(i), hlc)));
+            }
+        }
+        ids
+    }
+
+    #[test]
+    fn unique_across_nodes_and_restarts() {
+        for strategy in [IdStrategy::Counter, IdStrategy::Snowflake, IdStrategy::Hlc] {
+            runtime::install_virtual(0, Duration::from_millis(IdGenerator::SNOWFLAKE_EPOCH_MS));
+            // each run restarts in the millisecond the last was handing out ids in
+            let mut ids = run(strategy, 100, 0, &[0]);
+            ids.extend(run(strategy, 100, 1, &[1, 2]));
+            ids.extend(run(strategy, 100, 3, &[3]));
+            runtime::uninstall_virtual();
+            let unique: HashSet<&String> = ids.iter().collect();
+            assert_eq!(ids.len(), 1200);
+            assert_eq!(unique.len(), ids.len(), "{:?}", strategy);
+        }
+    }
+
+    #[test]
+    fn snowflake_waits_out_its_boot_millisecond_and_full_ones() {
+        runtime::install_virtual(0, Duration::from_millis(IdGenerator::SNOWFLAKE_EPOCH_MS));
+        let (n0, mut hlc) = (NodeId::Node(0), Hlc::default());
+        let mut generator = IdGenerator::new(IdStrategy::Snowflake);
+        let ids: Vec<u64> = (0..1 << IdGenerator::SEQUENCE_BITS)
+            .map(|_| generator.next(&n0, &mut hlc).parse().unwrap())
+            .collect();
+        assert_eq!(runtime::now(), Duration::from_millis(1));
+        assert!(ids.windows(2).all(|x| x[0] < x[1]));
+        let next: u64 = generator.next(&n0, &mut hlc).parse().unwrap();
+        assert_eq!(runtime::now(), Duration::from_millis(2));
+        assert!(next > ids[ids.len() - 1]);
+        runtime::uninstall_virtual();
+    }
+
+    #[test]
+    fn hlc_ids_sort_in_order_made() {
+        runtime::install_virtual(0, Duration::from_millis(IdGenerator::SNOWFLAKE_EPOCH_MS));
+        let (n0, mut hlc) = (NodeId::Node(0), Hlc::default());
+        let mut generator = IdGenerator::new(IdStrategy::Hlc);
+        let mut ids: Vec<String> = Vec::new();
+        for ms in 0..3 {
+            runtime::advance_to(Duration::from_millis(ms));
+            ids.extend((0..100).map(|_| generator.next(&n0, &mut hlc)));
+        }
+        assert!(ids.windows(2).all(|x| x[0] < x[1]));
+        assert!(ids.iter().all(|x| x.len() < 36), "{}", ids[0]);
+        runtime::uninstall_virtual();
+    }
+}
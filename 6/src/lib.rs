@@ -0,0 +1,50 @@
+use log::LevelFilter;
+use log4rs::append::file::FileAppender;
+use log4rs::config::{Appender, Root};
+use log4rs::encode::pattern::PatternEncoder;
+use log4rs::Config;
+
+pub mod causal;
+pub mod checker;
+pub mod clocks;
+pub mod config;
+pub mod crdt;
+pub mod edn;
+pub mod election;
+pub mod failure_detector;
+pub mod history;
+pub mod ids;
+pub mod membership;
+pub mod message;
+pub mod metrics;
+pub mod nemesis;
+pub mod node_id;
+pub mod plumtree;
+pub mod raft;
+pub mod runtime;
+pub mod sim;
+pub mod state;
+pub mod total_order;
+pub mod trace;
+
+pub fn setup_logging(pre: Option<String>) {
+    let location = String::from("/home/rcarrier/Projects/rusty-glomers/rusty-glomers.log");
+    let mut pattern = String::from("{l} - {m}\n");
+    if let Some(f) = pre {
+        pattern = format!("{{l}} - [{}] {{m}}\n", f);
+    }
+    // no log file is fine, as is a second node in the same process (the simulator)
+    // finding logging already set up
+    let Ok(logfile) = FileAppender::builder()
+        .encoder(Box::new(PatternEncoder::new(&pattern)))
+        .build(location)
+    else {
+        return;
+    };
+
+    let config = Config::builder()
+        .appender(Appender::builder().build("logfile", Box::new(logfile)))
+        .build(Root::builder().appender("logfile").build(LevelFilter::Info))
+        .unwrap();
+    let _ = log4rs::init_config(config);
+}
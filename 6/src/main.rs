@@ -0,0 +1,74 @@
+use rusty_glomers::config::Config;
+use rusty_glomers::message::MaelstromMessage;
+use rusty_glomers::state::State;
+use rusty_glomers::trace::{self, Recorder};
+use tokio::io::{self, AsyncBufReadExt, BufReader};
+use tokio::time;
+
+use std::env;
+use std::process;
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() {
+    if let Ok(path) = env::var(Config::REPLAY_VAR) {
+        replay(&path);
+        return;
+    }
+    let config = Config::from_env();
+    let mut recorder = config
+        .trace
+        .as_ref()
+        .map(|path| Recorder::create(path, &config).unwrap());
+    let mut state: State = State::new(config);
+    input_loop(&mut state, &mut recorder).await;
+}
+
+async fn input_loop(state: &mut State, recorder: &mut Option<Recorder>) {
+    let mut lines_stream = BufReader::new(io::stdin()).lines();
+    let mut tick = time::interval(Duration::from_millis(State::LOOP_DELAY_MS));
+    loop {
+        tokio::select! {
+            line = lines_stream.next_line() => {
+                match line {
+                    Ok(Some(valid_input)) => match recorder {
+                        Some(recorder) => recorder.input(state, valid_input),
+                        None => {
+                            let msg: MaelstromMessage = serde_json::from_str(&valid_input).unwrap();
+                            state.handle(msg);
+                        }
+                    },
+                    Ok(None) => {
+                        // stderr ends up in Maelstrom's per-node logs
+                        eprintln!("metrics: {}", state.metrics.to_json());
+                        return;
+                    }
+                    Err(_) => {}
+                }
+            },
+            _ = tick.tick() => match recorder {
+                Some(recorder) => recorder.tick(state),
+                None => state.tick(),
+            },
+        }
+    }
+}
+
+/// Replays a trace, printing each input whose output differs, and fails if any did.
+fn replay(path: &str) {
+    let trace = trace::from_json_lines(&std::fs::read_to_string(path).unwrap()).unwrap();
+    let report = trace::replay(&trace);
+    for mismatch in report.mismatches.iter() {
+        println!("at {:?} after {:?}", mismatch.time, mismatch.input);
+        mismatch.expected.iter().for_each(|x| println!("  - {}", x));
+        mismatch.actual.iter().for_each(|x| println!("  + {}", x));
+    }
+    println!(
+        "{} inputs replayed, {} differed",
+        report.inputs,
+        report.mismatches.len()
+    );
+    if !report.mismatches.is_empty() {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,394 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    message::{MaelstromMessage, MaelstromMessageBody, MessageType, MsgIds},
+    node_id::NodeId,
+    runtime,
+};
+
+/// Ordered so that, at the same incarnation, worse news wins.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Alive,
+    Suspect,
+    Dead,
+}
+
+/// What a node believes about one member. Only the member itself bumps its incarnation,
+/// to refute a suspicion (or a death) it hears about.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Member {
+    pub incarnation: u64,
+    pub status: Status,
+}
+
+/// A probe waiting on an ack.
+#[derive(Debug, Clone)]
+pub struct Probe {
+    pub target: NodeId,
+    pub msg_id: usize,
+    pub sent_at: Duration,
+    pub acked: bool,
+    /// whether others have been asked to ping the target for us yet
+    pub indirect: bool,
+}
+
+/// SWIM failure detection (Das et al.). Every period a node pings the next peer in its
+/// rotation; if there's no ack in time it asks a few others to ping that peer for it,
+/// and if the period ends without any ack the peer becomes suspect. Suspects that don't
+/// refute the suspicion within the timeout are declared dead.
+///
+/// Every ping and ack carries the sender's whole member table, which is small enough
+/// with Maelstrom's cluster sizes and spreads news as fast as piggybacking would. Dead
+/// members stay in the rotation so a partitioned node that comes back hears it was
+/// declared dead, refutes it, and is alive again.
+pub struct Swim {
+    pub node_id: NodeId,
+    pub members: BTreeMap<NodeId, Member>,
+    pub suspected_at: BTreeMap<NodeId, Duration>,
+    pub probe: Option<Probe>,
+    pub next_probe: Duration,
+    /// our ping's msg_id -> who asked us to ping, and the msg_id they asked under
+    pub relaying: HashMap<usize, (NodeId, usize)>,
+    rotation: usize,
+    msg_ids: MsgIds,
+}
+
+impl Swim {
+    pub const PROBE_PERIOD_MS: u64 = 200;
+    pub const PING_TIMEOUT_MS: u64 = 80;
+    pub const SUSPECT_TIMEOUT_MS: u64 = 600;
+    /// how many others to ask for an indirect ping
+    pub const PING_REQ_FANOUT: usize = 2;
+
+    pub fn new(node_id: NodeId, node_ids: &[NodeId], msg_ids: MsgIds) -> Self {
+        let alive = Member {
+            incarnation: 0,
+            status: Status::Alive,
+        };
+        Swim {
+            members: node_ids.iter().map(|x| (x.clone(), alive)).collect(),
+            node_id,
+            suspected_at: BTreeMap::new(),
+            probe: None,
+            next_probe: runtime::now(),
+            relaying: HashMap::new(),
+            rotation: runtime::gen_range(0..node_ids.len().max(1) as u64) as usize,
+            msg_ids,
+        }
+    }
+
+    pub fn status(&self, node_id: &NodeId) -> Option<Status> {
+        self.members.get(node_id).map(|x| x.status)
+    }
+
+    /// Members in `status`, this node included.
+    pub fn view(&self, status: Status) -> Vec<NodeId> {
+        self.members
+            .iter()
+            .filter(|(_, x)| x.status == status)
+            .map(|(node_id, _)| node_id.clone())
+            .collect()
+    }
+
+    /// Peers worth talking to: alive or only suspected.
+    pub fn is_reachable(&self, node_id: &NodeId) -> bool {
+        self.status(node_id) != Some(Status::Dead)
+    }
+
+    pub fn tick(&mut self) -> Vec<MaelstromMessage> {
+        let now = runtime::now();
+        let mut out = Vec::new();
+        let timeout = Duration::from_millis(Swim::SUSPECT_TIMEOUT_MS);
+        let expired: Vec<NodeId> = self
+            .suspected_at
+            .iter()
+            .filter(|(_, at)| now >= **at + timeout)
+            .map(|(node_id, _)| node_id.clone())
+            .collect();
+        for node_id in expired {
+            self.set(&node_id, Status::Dead);
+        }
+
+        if now >= self.next_probe {
+            if let Some(probe) = self.probe.take().filter(|x| !x.acked) {
+                self.set(&probe.target, Status::Suspect);
+            }
+            self.next_probe = now + Duration::from_millis(Swim::PROBE_PERIOD_MS);
+            out.extend(self.start_probe());
+        }
+
+        let ping_timeout = Duration::from_millis(Swim::PING_TIMEOUT_MS);
+        let overdue = self
+            .probe
+            .as_ref()
+            .filter(|x| !x.acked && !x.indirect && now >= x.sent_at + ping_timeout)
+            .map(|x| (x.target.clone(), x.msg_id));
+        if let Some((target, msg_id)) = overdue {
+            self.probe.as_mut().unwrap().indirect = true;
+            let mut helpers: Vec<NodeId> = self
+                .members
+                .iter()
+                .filter(|(x, m)| **x != self.node_id && **x != target && m.status == Status::Alive)
+                .map(|(x, _)| x.clone())
+                .collect();
+            for _ in 0..Swim::PING_REQ_FANOUT.min(helpers.len()) {
+                let helper = helpers.remove(runtime::gen_range(0..helpers.len() as u64) as usize);
+                let body = MaelstromMessageBody {
+                    msg_id: Some(msg_id),
+                    target: Some(target.clone()),
+                    ..MaelstromMessageBody::new(MessageType::PingReq)
+                };
+                out.push(self.message(&helper, body));
+            }
+        }
+        out
+    }
+
+    fn start_probe(&mut self) -> Vec<MaelstromMessage> {
+        let peers: Vec<NodeId> = self
+            .members
+            .keys()
+            .filter(|x| **x != self.node_id)
+            .cloned()
+            .collect();
+        if peers.is_empty() {
+            return Vec::new();
+        }
+        self.rotation += 1;
+        let target = peers[self.rotation % peers.len()].clone();
+        let msg_id = self.ping_id();
+        self.probe = Some(Probe {
+            target: target.clone(),
+            msg_id,
+            sent_at: runtime::now(),
+            acked: false,
+            indirect: false,
+        });
+        vec![self.ping(&target, msg_id)]
+    }
+
+    pub fn handle_ping(&mut self, msg: &MaelstromMessage) -> Vec<MaelstromMessage> {
+        self.merge(msg);
+        let body = MaelstromMessageBody {
+            members: Some(self.members.clone()),
+            ..msg.body.reply(MessageType::PingOk)
+        };
+        vec![self.message(&msg.src, body)]
+    }
+
+    pub fn handle_ping_ok(&mut self, msg: &MaelstromMessage) -> Vec<MaelstromMessage> {
+        self.merge(msg);
+        let in_reply_to = msg.body.in_reply_to.unwrap_or_default();
+        if let Some(probe) = self.probe.as_mut().filter(|x| x.msg_id == in_reply_to) {
+            probe.acked = true;
+        }
+        let Some((requester, msg_id)) = self.relaying.remove(&in_reply_to) else {
+            return Vec::new();
+        };
+        let body = MaelstromMessageBody {
+            in_reply_to: Some(msg_id),
+            target: Some(msg.src.clone()),
+            members: Some(self.members.clone()),
+            ..MaelstromMessageBody::new(MessageType::PingReqOk)
+        };
+        vec![self.message(&requester, body)]
+    }
+
+    /// Pings the target on someone else's behalf, passing the ack back when it comes.
+    pub fn handle_ping_req(&mut self, msg: &MaelstromMessage) -> Vec<MaelstromMessage> {
+        self.merge(msg);
+        let target = msg.body.target.clone().unwrap();
+        let msg_id = self.ping_id();
+        self.relaying
+            .insert(msg_id, (msg.src.clone(), msg.body.msg_id.unwrap()));
+        vec![self.ping(&target, msg_id)]
+    }
+
+    pub fn handle_ping_req_ok(&mut self, msg: &MaelstromMessage) {
+        self.merge(msg);
+        let in_reply_to = msg.body.in_reply_to.unwrap_or_default();
+        if let Some(probe) = self.probe.as_mut().filter(|x| x.msg_id == in_reply_to) {
+            probe.acked = true;
+        }
+    }
+
+    /// Takes in someone's member table, keeping the later incarnation or, at the same
+    /// one, the worse status. News of our own suspicion or death is refuted by moving to
+    /// the next incarnation.
+    fn merge(&mut self, msg: &MaelstromMessage) {
+        let Some(remote) = &msg.body.members else {
+            return;
+        };
+        for (node_id, theirs) in remote.iter() {
+            let Some(ours) = self.members.get(node_id).copied() else {
+                continue;
+            };
+            if *node_id == self.node_id {
+                if theirs.status != Status::Alive && theirs.incarnation >= ours.incarnation {
+                    self.members.insert(
+                        node_id.clone(),
+                        Member {
+                            incarnation: theirs.incarnation + 1,
+                            status: Status::Alive,
+                        },
+                    );
+                }
+            } else if *theirs > ours {
+                self.members.insert(node_id.clone(), *theirs);
+                self.timer(node_id, theirs.status);
+            }
+        }
+    }
+
+    /// Our own verdict on a peer, at the incarnation we last heard of.
+    fn set(&mut self, node_id: &NodeId, status: Status) {
+        let Some(member) = self.members.get_mut(node_id) else {
+            return;
+        };
+        if status > member.status {
+            member.status = status;
+            self.timer(node_id, status);
+        }
+    }
+
+    fn timer(&mut self, node_id: &NodeId, status: Status) {
+        if status == Status::Suspect {
+            self.suspected_at
+                .entry(node_id.clone())
+                .or_insert_with(runtime::now);
+        } else {
+            self.suspected_at.remove(node_id);
+        }
+    }
+
+    fn ping_id(&mut self) -> usize {
+        self.msg_ids.next()
+    }
+
+    fn ping(&self, target: &NodeId, msg_id: usize) -> MaelstromMessage {
+        let body = MaelstromMessageBody {
+            msg_id: Some(msg_id),
+            members: Some(self.members.clone()),
+            ..MaelstromMessageBody::new(MessageType::Ping)
+        };
+        self.message(target, body)
+    }
+
+    fn message(&self, dest: &NodeId, body: MaelstromMessageBody) -> MaelstromMessage {
+        MaelstromMessage {
+            src: self.node_id.clone(),
+            dest: dest.clone(),
+            body,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        nemesis::Fault,
+        sim::{cluster, Simulator},
+    };
+
+    #[test]
+    fn refutes_its_own_suspicion_with_a_new_incarnation() {
+        runtime::install_virtual(0, Duration::ZERO);
+        let (n0, n1) = (NodeId::Node(0), NodeId::Node(1));
+        let mut swim = Swim::new(n0.clone(), &[n0.clone(), n1.clone()], MsgIds::default());
+        let suspect = Member {
+            incarnation: 0,
+            status: Status::Suspect,
+        };
+        let ping = MaelstromMessage {
+            src: n1.clone(),
+            dest: n0.clone(),
+            body: MaelstromMessageBody {
+                msg_id: Some(1),
+                members: Some(BTreeMap::from([(n0.clone(), suspect)])),
+                ..MaelstromMessageBody::new(MessageType::Ping)
+            },
+        };
+        let reply = swim.handle_ping(&ping);
+        assert_eq!(reply[0].body.msg_type, MessageType::PingOk);
+        let ours = reply[0].body.members.as_ref().unwrap()[&n0];
+        assert_eq!((ours.incarnation, ours.status), (1, Status::Alive));
+
+        // a stale alive doesn't override a suspicion at the same incarnation
+        swim.set(&n1, Status::Suspect);
+        let alive = Member {
+            incarnation: 0,
+            status: Status::Alive,
+        };
+        swim.merge(&MaelstromMessage {
+            body: MaelstromMessageBody {
+                members: Some(BTreeMap::from([(n1.clone(), alive)])),
+                ..ping.body.clone()
+            },
+            ..ping.clone()
+        });
+        assert_eq!(swim.status(&n1), Some(Status::Suspect));
+        runtime::advance_to(Duration::from_millis(Swim::SUSPECT_TIMEOUT_MS));
+        swim.tick();
+        assert_eq!(swim.view(Status::Dead), [n1]);
+        runtime::uninstall_virtual();
+    }
+
+    #[test]
+    fn declares_an_isolated_node_dead_and_takes_it_back() {
+        let mut sim = cluster::start(
+            4,
+            Config {
+                membership: true,
+                ..Config::default()
+            },
+        );
+        let second = Duration::from_secs(1);
+        let n3 = NodeId::Node(3);
+        sim.nemesis
+            .add(second, second * 3, Fault::Isolate(Some(n3.clone())));
+        let status = |sim: &Simulator, node_id: &NodeId| {
+            let swim = sim.nodes[node_id].membership.as_ref().unwrap();
+            (swim.status(&n3), swim.view(Status::Alive).len())
+        };
+        sim.run_for(second);
+        for node_id in sim.node_ids() {
+            assert_eq!(
+                status(&sim, &node_id),
+                (Some(Status::Alive), 4),
+                "{}",
+                node_id
+            );
+        }
+        sim.run_for(second * 2);
+        for node_id in [NodeId::Node(0), NodeId::Node(1), NodeId::Node(2)] {
+            assert_eq!(status(&sim, &node_id).0, Some(Status::Dead), "{}", node_id);
+        }
+        // n3 in turn has given up on everyone else
+        assert_eq!(status(&sim, &n3).1, 1);
+        // what's for n3 now waits for it to come back
+        cluster::broadcast(&mut sim, &NodeId::Node(0), 1);
+        sim.run_for(second / 10);
+        let n0 = &sim.nodes[&NodeId::Node(0)];
+        assert!(n0.awaiting_ack.iter().all(|x| x.msg.dest != n3));
+        assert_eq!(n0.parked[&n3].len(), 1);
+        sim.run_for(second * 3);
+        assert_eq!(sim.nodes[&n3].delivery_order, [1]);
+        for node_id in sim.node_ids() {
+            assert_eq!(
+                status(&sim, &node_id),
+                (Some(Status::Alive), 4),
+                "{}",
+                node_id
+            );
+        }
+    }
+}
@@ -0,0 +1,463 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    fmt,
+    rc::Rc,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    clocks::{HlcTimestamp, VectorClock},
+    config::BroadcastMode,
+    membership::Member,
+    node_id::NodeId,
+    raft::LogEntry,
+    state::State,
+};
+
+#[serde_with::skip_serializing_none]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MaelstromMessage {
+    pub src: NodeId,
+    pub dest: NodeId,
+    pub body: MaelstromMessageBody,
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MaelstromMessageBody {
+    #[serde(rename = "type")]
+    pub msg_type: MessageType,
+    pub msg_id: Option<usize>,
+    pub in_reply_to: Option<usize>,
+    pub echo: Option<String>,
+    pub node_id: Option<NodeId>,
+    pub id: Option<String>,
+    pub node_ids: Option<Vec<NodeId>>,
+    pub message: Option<usize>,
+    /// where a broadcast value entered the cluster, and what it causally follows there
+    pub origin: Option<NodeId>,
+    pub deps: Option<VectorClock>,
+    /// a broadcast value's place in the total order, and the sequencer token's pass
+    pub seq: Option<usize>,
+    pub round: Option<usize>,
+    /// who a `ping_req` asks to have pinged, and the sender's view of the membership
+    pub target: Option<NodeId>,
+    pub members: Option<BTreeMap<NodeId, Member>>,
+    pub messages: Option<Vec<usize>>,
+    pub topology: Option<BTreeMap<NodeId, Vec<NodeId>>>,
+    pub txn: Option<Vec<MicroOp>>,
+    pub timestamp: Option<usize>,
+    pub key: Option<usize>,
+    pub value: Option<Value>,
+    pub from: Option<usize>,
+    pub to: Option<usize>,
+    pub create_if_not_exists: Option<bool>,
+    pub code: Option<usize>,
+    pub text: Option<String>,
+    pub term: Option<usize>,
+    pub vote_granted: Option<bool>,
+    pub last_log_index: Option<usize>,
+    pub last_log_term: Option<usize>,
+    pub prev_log_index: Option<usize>,
+    pub prev_log_term: Option<usize>,
+    pub entries: Option<Vec<LogEntry>>,
+    pub leader_commit: Option<usize>,
+    pub success: Option<bool>,
+    pub match_index: Option<usize>,
+    /// on a failed append, where the follower's log stops agreeing with the leader's: the
+    /// term of its entry at `prev_log_index` (if it has one) and the first index it has
+    /// from that term, or the end of its log
+    pub conflict_term: Option<usize>,
+    pub conflict_index: Option<usize>,
+    /// the fencing token of the leadership an `election` or `coordinator` speaks for
+    pub token: Option<u64>,
+    pub element: Option<usize>,
+    pub delta: Option<i64>,
+    pub crdt: Option<Value>,
+    pub metrics: Option<Value>,
+    pub debug: Option<Value>,
+    pub lamport: Option<u64>,
+    pub vclock: Option<VectorClock>,
+    pub hlc: Option<HlcTimestamp>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageType {
+    Init,
+    InitOk,
+    Echo,
+    EchoOk,
+    Generate,
+    GenerateOk,
+    Broadcast,
+    BroadcastOk,
+    Read,
+    ReadOk,
+    Topology,
+    TopologyOk,
+    Txn,
+    TxnOk,
+    Write,
+    WriteOk,
+    Cas,
+    CasOk,
+    Error,
+    RequestVote,
+    RequestVoteOk,
+    AppendEntries,
+    AppendEntriesOk,
+    Add,
+    AddOk,
+    Gossip,
+    Metrics,
+    MetricsOk,
+    DebugState,
+    DebugStateOk,
+    DebugPeers,
+    DebugPeersOk,
+    DebugPending,
+    DebugPendingOk,
+    SequencerToken,
+    SequencerTokenOk,
+    #[serde(rename = "ihave")]
+    IHave,
+    #[serde(rename = "ihave_ok")]
+    IHaveOk,
+    Graft,
+    Prune,
+    Ping,
+    PingOk,
+    PingReq,
+    PingReqOk,
+    Heartbeat,
+    Election,
+    ElectionOk,
+    Coordinator,
+}
+
+impl MessageType {
+    /// The request an ack the node waits on answers.
+    pub fn acks(&self) -> Option<MessageType> {
+        match self {
+            MessageType::BroadcastOk => Some(MessageType::Broadcast),
+            MessageType::TxnOk => Some(MessageType::Txn),
+            MessageType::SequencerTokenOk => Some(MessageType::SequencerToken),
+            MessageType::IHaveOk => Some(MessageType::IHave),
+            _ => None,
+        }
+    }
+}
+
+/// The node's msg_id counter. Everything on the node that sends requests holds a clone
+/// and they all count together, so no two requests share an id.
+#[derive(Debug, Clone, Default)]
+pub struct MsgIds(Rc<Cell<usize>>);
+
+impl MsgIds {
+    pub fn next(&self) -> usize {
+        let id = self.0.get() + 1;
+        self.0.set(id);
+        id
+    }
+
+    /// The last id handed out.
+    pub fn last(&self) -> usize {
+        self.0.get()
+    }
+}
+
+/// A single txn micro-op, on the wire as `["r", key, value]` or `["w", key, value]`.
+/// Reads come in with a `null` value and go back out with whatever was read.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(try_from = "RawMicroOp", into = "RawMicroOp")]
+pub enum MicroOp {
+    Read(usize, Option<usize>),
+    Write(usize, usize),
+}
+
+#[derive(Serialize, Deserialize)]
+struct RawMicroOp(String, usize, Option<usize>);
+
+impl TryFrom<RawMicroOp> for MicroOp {
+    type Error = String;
+    fn try_from(raw: RawMicroOp) -> Result<Self, Self::Error> {
+        match (raw.0.as_str(), raw.2) {
+            ("r", value) => Ok(MicroOp::Read(raw.1, value)),
+            ("w", Some(value)) => Ok(MicroOp::Write(raw.1, value)),
+            ("w", None) => Err(format!("write to {} without a value", raw.1)),
+            (f, _) => Err(format!("unknown micro-op function: {}", f)),
+        }
+    }
+}
+
+impl From<MicroOp> for RawMicroOp {
+    fn from(op: MicroOp) -> Self {
+        match op {
+            MicroOp::Read(key, value) => RawMicroOp(String::from("r"), key, value),
+            MicroOp::Write(key, value) => RawMicroOp(String::from("w"), key, Some(value)),
+        }
+    }
+}
+
+thread_local! {
+    /// When set, `post` collects messages here instead of printing them.
+    static CAPTURE: RefCell<Option<Vec<MaelstromMessage>>> = const { RefCell::new(None) };
+}
+
+impl fmt::Display for MaelstromMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", serde_json::to_string(self).unwrap())
+    }
+}
+
+impl MaelstromMessage {
+    pub const REPOST_DELAY_MS: u128 = 30;
+    pub fn post(&self) {
+        let captured = CAPTURE.with(|c| match c.borrow_mut().as_mut() {
+            Some(out) => {
+                out.push(self.clone());
+                true
+            }
+            None => false,
+        });
+        if !captured {
+            println!("{}", serde_json::to_string(self).unwrap());
+        }
+    }
+    /// Runs `f`, returning everything it posted on this thread rather than writing it to
+    /// stdout. Lets several nodes share one process.
+    /// Captures nest, the inner one getting only what was posted inside it.
+    pub fn capture(f: impl FnOnce()) -> Vec<MaelstromMessage> {
+        let outer = CAPTURE.with(|c| c.replace(Some(Vec::new())));
+        f();
+        CAPTURE.with(|c| c.replace(outer)).unwrap()
+    }
+    pub fn get_broadcast_msg(state: &State, msg: MaelstromMessage) -> Vec<Self> {
+        let temp: Vec<Self> = state
+            .topology
+            .get(&state.node_id)
+            .unwrap()
+            .iter()
+            .filter(|x| **x != msg.src)
+            .map(|dst_node| MaelstromMessage {
+                src: state.node_id.clone(),
+                dest: dst_node.clone(),
+                body: MaelstromMessageBody::get_broadcast_body(state.msg_ids.next(), &msg),
+            })
+            .collect();
+        temp.iter()
+            .for_each(|x| log::info!("Broadcasting {} to {}", msg.body.message.unwrap(), x.dest));
+        temp
+    }
+    /// Writes from a local txn, sent straight to every other node to be applied last-writer-wins.
+    pub fn get_replicate_msgs(state: &State, writes: Vec<MicroOp>, timestamp: usize) -> Vec<Self> {
+        state
+            .node_ids
+            .iter()
+            .filter(|x| **x != state.node_id)
+            .map(|dst_node| MaelstromMessage {
+                src: state.node_id.clone(),
+                dest: dst_node.clone(),
+                body: MaelstromMessageBody::get_replicate_body(
+                    state.msg_ids.next(),
+                    writes.clone(),
+                    timestamp,
+                ),
+            })
+            .collect()
+    }
+    pub fn get_response(self, state: &State) -> Result<MaelstromMessage, String> {
+        let body = self.body.get_response(state)?;
+        Ok(MaelstromMessage {
+            src: self.dest,
+            dest: self.src,
+            body,
+        })
+    }
+}
+
+impl MaelstromMessageBody {
+    pub const TIMEOUT: usize = 0;
+    pub const NOT_SUPPORTED: usize = 10;
+    pub const TEMPORARILY_UNAVAILABLE: usize = 11;
+    pub const MALFORMED_REQUEST: usize = 12;
+    pub const KEY_DOES_NOT_EXIST: usize = 20;
+    pub const PRECONDITION_FAILED: usize = 22;
+
+    pub fn new(msg_type: MessageType) -> Self {
+        MaelstromMessageBody {
+            msg_type,
+            msg_id: None,
+            in_reply_to: None,
+            echo: None,
+            node_id: None,
+            id: None,
+            node_ids: None,
+            message: None,
+            origin: None,
+            deps: None,
+            seq: None,
+            round: None,
+            target: None,
+            members: None,
+            messages: None,
+            topology: None,
+            txn: None,
+            timestamp: None,
+            key: None,
+            value: None,
+            from: None,
+            to: None,
+            create_if_not_exists: None,
+            code: None,
+            text: None,
+            term: None,
+            vote_granted: None,
+            last_log_index: None,
+            last_log_term: None,
+            prev_log_index: None,
+            prev_log_term: None,
+            entries: None,
+            leader_commit: None,
+            success: None,
+            match_index: None,
+            conflict_term: None,
+            conflict_index: None,
+            token: None,
+            element: None,
+            delta: None,
+            crdt: None,
+            metrics: None,
+            debug: None,
+            lamport: None,
+            vclock: None,
+            hlc: None,
+        }
+    }
+    /// Empty body of `msg_type` answering this one. Replies aren't answered in turn, so
+    /// they go without a msg_id of their own.
+    pub fn reply(&self, msg_type: MessageType) -> Self {
+        MaelstromMessageBody {
+            in_reply_to: self.msg_id,
+            ..MaelstromMessageBody::new(msg_type)
+        }
+    }
+    pub fn error(&self, code: usize, text: &str) -> Self {
+        MaelstromMessageBody {
+            code: Some(code),
+            text: Some(text.to_string()),
+            ..self.reply(MessageType::Error)
+        }
+    }
+    pub fn get_broadcast_body(msg_id: usize, msg: &MaelstromMessage) -> Self {
+        MaelstromMessageBody {
+            msg_id: Some(msg_id),
+            message: msg.body.message,
+            origin: msg.body.origin.clone(),
+            deps: msg.body.deps.clone(),
+            ..MaelstromMessageBody::new(MessageType::Broadcast)
+        }
+    }
+    pub fn get_replicate_body(msg_id: usize, writes: Vec<MicroOp>, timestamp: usize) -> Self {
+        MaelstromMessageBody {
+            msg_id: Some(msg_id),
+            txn: Some(writes),
+            timestamp: Some(timestamp),
+            ..MaelstromMessageBody::new(MessageType::Txn)
+        }
+    }
+    pub fn get_response(self, state: &State) -> Result<MaelstromMessageBody, String> {
+        match self.msg_type {
+            MessageType::InitOk
+            | MessageType::EchoOk
+            | MessageType::TopologyOk
+            | MessageType::GenerateOk
+            | MessageType::ReadOk
+            | MessageType::BroadcastOk
+            | MessageType::TxnOk
+            | MessageType::WriteOk
+            | MessageType::CasOk
+            | MessageType::Error
+            | MessageType::RequestVoteOk
+            | MessageType::AppendEntriesOk
+            | MessageType::AddOk
+            | MessageType::MetricsOk
+            | MessageType::DebugStateOk
+            | MessageType::DebugPeersOk
+            | MessageType::DebugPendingOk
+            | MessageType::SequencerTokenOk
+            | MessageType::IHaveOk
+            | MessageType::PingOk
+            | MessageType::PingReqOk
+            | MessageType::ElectionOk => Err(String::from("can't handle response")),
+            // replies to these go out from the raft log once committed
+            MessageType::Write
+            | MessageType::Cas
+            | MessageType::RequestVote
+            | MessageType::AppendEntries
+            | MessageType::Gossip
+            | MessageType::Graft
+            | MessageType::Prune
+            | MessageType::Ping
+            | MessageType::PingReq
+            | MessageType::Heartbeat
+            | MessageType::Coordinator => Err(String::from("will not handle response")),
+            // answered in dispatch, which knows whether the crdt took it
+            MessageType::Add => Err(String::from("will not handle response")),
+            MessageType::Read if self.key.is_some() => {
+                Err(String::from("will not handle response"))
+            }
+            MessageType::Broadcast if state.config.broadcast == BroadcastMode::Raft => {
+                Err(String::from("will not handle response"))
+            }
+            MessageType::Init => Ok(self.reply(MessageType::InitOk)),
+            MessageType::Echo => Ok(MaelstromMessageBody {
+                echo: self.echo.clone(),
+                ..self.reply(MessageType::EchoOk)
+            }),
+            // the id was made by the node's generator on the way in
+            MessageType::Generate => Ok(MaelstromMessageBody {
+                id: self.id.clone(),
+                ..self.reply(MessageType::GenerateOk)
+            }),
+            MessageType::Broadcast => Ok(self.reply(MessageType::BroadcastOk)),
+            MessageType::SequencerToken => Ok(self.reply(MessageType::SequencerTokenOk)),
+            MessageType::IHave => Ok(self.reply(MessageType::IHaveOk)),
+            MessageType::Election => Ok(self.reply(MessageType::ElectionOk)),
+            MessageType::Read if state.crdt.is_some() => Ok(MaelstromMessageBody {
+                value: state.crdt.as_ref().map(|x| x.read()),
+                ..self.reply(MessageType::ReadOk)
+            }),
+            MessageType::Read => Ok(MaelstromMessageBody {
+                messages: Some(state.delivery_order.clone()),
+                ..self.reply(MessageType::ReadOk)
+            }),
+            MessageType::Topology => Ok(self.reply(MessageType::TopologyOk)),
+            MessageType::Metrics => Ok(MaelstromMessageBody {
+                metrics: Some(state.metrics.to_json()),
+                ..self.reply(MessageType::MetricsOk)
+            }),
+            MessageType::DebugState => Ok(MaelstromMessageBody {
+                debug: Some(state.debug_state()),
+                ..self.reply(MessageType::DebugStateOk)
+            }),
+            MessageType::DebugPeers => Ok(MaelstromMessageBody {
+                debug: Some(state.debug_peers()),
+                ..self.reply(MessageType::DebugPeersOk)
+            }),
+            MessageType::DebugPending => Ok(MaelstromMessageBody {
+                debug: Some(state.debug_pending()),
+                ..self.reply(MessageType::DebugPendingOk)
+            }),
+            // the ops have already been applied in State::handle, reads included
+            MessageType::Txn => Ok(MaelstromMessageBody {
+                txn: self.txn.clone(),
+                ..self.reply(MessageType::TxnOk)
+            }),
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use serde::Serialize;
+
+use crate::{message::MaelstromMessage, node_id::NodeId};
+
+/// Handler latencies bucketed by powers of two, in microseconds. Bucket `b` counts the
+/// samples under `2^b` µs that didn't fit the one before.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Histogram {
+    pub count: u64,
+    pub sum_us: u64,
+    pub max_us: u64,
+    pub buckets: BTreeMap<u32, u64>,
+}
+
+impl Histogram {
+    pub fn record(&mut self, sample: Duration) {
+        let us = sample.as_micros() as u64;
+        self.count += 1;
+        self.sum_us += us;
+        self.max_us = self.max_us.max(us);
+        *self
+            .buckets
+            .entry(u64::BITS - us.leading_zeros())
+            .or_default() += 1;
+    }
+}
+
+/// How long a queue is now, and the longest it has been.
+#[derive(Serialize, Debug, Clone, Copy, Default)]
+pub struct Gauge {
+    pub current: usize,
+    pub max: usize,
+}
+
+/// Counters for everything a node sends and receives, for judging strategies on the
+/// numbers 3d/3e are graded on. Counts are keyed by message type, then peer.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Metrics {
+    pub sent: BTreeMap<String, BTreeMap<String, u64>>,
+    pub received: BTreeMap<String, BTreeMap<String, u64>>,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// requests received from clients
+    pub client_requests: u64,
+    /// messages sent to other nodes rather than clients
+    pub server_messages: u64,
+    pub retransmissions: u64,
+    /// times the elected leader (or its fencing token) changed, as this node saw it
+    pub leader_changes: u64,
+    /// runtime clock time spent handling each type of message, and in ticks, which is
+    /// always zero under virtual time so replaying a trace reports the same numbers
+    pub latency: BTreeMap<String, Histogram>,
+    pub queues: BTreeMap<String, Gauge>,
+}
+
+impl Metrics {
+    pub fn sent(&mut self, msg: &MaelstromMessage) {
+        Metrics::count(&mut self.sent, msg, &msg.dest);
+        self.bytes_sent += msg.to_string().len() as u64;
+        if !msg.dest.is_client() {
+            self.server_messages += 1;
+        }
+    }
+
+    pub fn received(&mut self, msg: &MaelstromMessage) {
+        Metrics::count(&mut self.received, msg, &msg.src);
+        self.bytes_received += msg.to_string().len() as u64;
+        if msg.src.is_client() {
+            self.client_requests += 1;
+        }
+    }
+
+    fn count(
+        counts: &mut BTreeMap<String, BTreeMap<String, u64>>,
+        msg: &MaelstromMessage,
+        peer: &NodeId,
+    ) {
+        let msg_type = serde_json::to_value(&msg.body.msg_type).unwrap();
+        *counts
+            .entry(msg_type.as_str().unwrap_or_default().to_string())
+            .or_default()
+            .entry(peer.to_string())
+            .or_default() += 1;
+    }
+
+    pub fn handled(&mut self, handler: &str, took: Duration) {
+        self.latency
+            .entry(handler.to_string())
+            .or_default()
+            .record(took);
+    }
+
+    pub fn queue(&mut self, name: &str, len: usize) {
+        let gauge = self.queues.entry(name.to_string()).or_default();
+        gauge.current = len;
+        gauge.max = gauge.max.max(len);
+    }
+
+    /// Inter-server messages per client request, what Maelstrom calls msgs-per-op.
+    pub fn msgs_per_op(&self) -> f64 {
+        self.server_messages as f64 / self.client_requests.max(1) as f64
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut json = serde_json::to_value(self).unwrap();
+        json["msgs_per_op"] = self.msgs_per_op().into();
+        json
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        config::Config,
+        message::{MaelstromMessageBody, MessageType},
+        node_id::NodeId,
+        sim::cluster::{self, TIMEOUT},
+    };
+
+    #[test]
+    fn counts_broadcast_traffic() {
+        let mut sim = cluster::start(3, Config::default());
+        cluster::broadcast(&mut sim, &NodeId::Node(0), 1);
+        sim.run_for(Duration::from_millis(200));
+        let metrics = sim
+            .call(
+                &NodeId::Node(0),
+                MaelstromMessageBody::new(MessageType::Metrics),
+                TIMEOUT,
+            )
+            .unwrap()
+            .body
+            .metrics
+            .unwrap();
+        assert_eq!(metrics["received"]["broadcast"]["c1"], 1);
+        assert_eq!(metrics["sent"]["broadcast_ok"]["c1"], 1);
+        for peer in ["n1", "n2"] {
+            assert!(metrics["sent"]["broadcast"][peer].as_u64().unwrap() >= 1);
+            assert!(metrics["received"]["broadcast_ok"][peer].as_u64().unwrap() >= 1);
+        }
+        assert_eq!(metrics["queues"]["awaiting_ack"]["current"], 0);
+        assert!(metrics["latency"]["broadcast"]["count"].as_u64().unwrap() >= 1);
+    }
+}
@@ -0,0 +1,228 @@
+use std::{collections::HashSet, time::Duration};
+
+use crate::{node_id::NodeId, runtime};
+
+/// Something going wrong with the simulated network or nodes. Partitions and crashes
+/// that don't name a node pick one (or a split) from the seeded rng when they start.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    /// Nodes are split into two random halves that can't reach each other.
+    PartitionHalves,
+    /// One node is cut off from everyone else.
+    Isolate(Option<NodeId>),
+    /// Two halves that can only talk through a single node sitting in both.
+    Bridge,
+    /// Each message is lost with this probability.
+    Drop(f64),
+    /// Each message is delivered twice with this probability.
+    Duplicate(f64),
+    /// Each message picks up a random extra delay of up to this much, so they arrive out of order.
+    Reorder(Duration),
+    /// Every message takes this much longer.
+    DelaySpike(Duration),
+    /// The node stops, losing its state, and is restarted fresh (and re-inited) afterwards.
+    Crash(Option<NodeId>),
+}
+
+#[derive(Debug, Clone)]
+pub struct NemesisEvent {
+    pub at: Duration,
+    pub duration: Duration,
+    pub fault: Fault,
+}
+
+/// A fault that has started, with whatever it picked at random settled.
+#[derive(Debug, Clone)]
+pub enum ActiveFault {
+    /// pairs of nodes that can't reach each other, both directions present
+    Partition(HashSet<(NodeId, NodeId)>),
+    Drop(f64),
+    Duplicate(f64),
+    Reorder(Duration),
+    DelaySpike(Duration),
+    Crash(NodeId),
+}
+
+/// What the network does to one message given the faults in play.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fate {
+    Lost,
+    /// one entry per copy delivered, the extra delay for each
+    Delivered(Vec<Duration>),
+}
+
+/// Schedule of faults for the simulator, started and healed as virtual time passes.
+#[derive(Debug, Clone, Default)]
+pub struct Nemesis {
+    pub schedule: Vec<NemesisEvent>,
+    /// index into `schedule` -> what it resolved to
+    pub active: Vec<(usize, ActiveFault)>,
+    /// indices into `schedule` that have started, healed or not
+    pub started: HashSet<usize>,
+}
+
+impl Nemesis {
+    pub fn add(&mut self, at: Duration, duration: Duration, fault: Fault) {
+        self.schedule.push(NemesisEvent {
+            at,
+            duration,
+            fault,
+        });
+    }
+
+    /// `fault` for `duration` out of every `interval`, like Maelstrom's `--nemesis-interval`,
+    /// starting after the first interval and stopping by `until`.
+    pub fn every(&mut self, interval: Duration, duration: Duration, until: Duration, fault: Fault) {
+        let mut at = interval;
+        while at + duration <= until {
+            self.add(at, duration, fault.clone());
+            at += interval;
+        }
+    }
+
+    /// Starts and heals faults for the current time. Returns the faults that just healed
+    /// so the simulator can restart crashed nodes. A fault that fits between two steps
+    /// still happens, starting and healing on the same step.
+    pub fn update(&mut self, node_ids: &[NodeId]) -> Vec<ActiveFault> {
+        let now = runtime::now();
+        for i in 0..self.schedule.len() {
+            if now < self.schedule[i].at || !self.started.insert(i) {
+                continue;
+            }
+            let fault = Nemesis::resolve(&self.schedule[i].fault, node_ids);
+            self.active.push((i, fault));
+        }
+        let (healed, active): (Vec<_>, Vec<_>) = self
+            .active
+            .drain(..)
+            .partition(|(i, _)| now >= self.schedule[*i].at + self.schedule[*i].duration);
+        self.active = active;
+        healed.into_iter().map(|(_, fault)| fault).collect()
+    }
+
+    fn resolve(fault: &Fault, node_ids: &[NodeId]) -> ActiveFault {
+        let pick = || node_ids[runtime::gen_range(0..node_ids.len() as u64) as usize].clone();
+        match fault {
+            Fault::PartitionHalves => {
+                let (a, b) = Nemesis::halves(node_ids);
+                ActiveFault::Partition(Nemesis::cut(&a, &b))
+            }
+            Fault::Isolate(node_id) => {
+                let node_id = node_id.clone().unwrap_or_else(pick);
+                let rest: Vec<NodeId> = node_ids
+                    .iter()
+                    .filter(|x| **x != node_id)
+                    .cloned()
+                    .collect();
+                ActiveFault::Partition(Nemesis::cut(&[node_id], &rest))
+            }
+            Fault::Bridge => {
+                let (mut a, b) = Nemesis::halves(node_ids);
+                // the bridge sits in neither half, so it's cut off from no one
+                a.pop();
+                ActiveFault::Partition(Nemesis::cut(&a, &b))
+            }
+            Fault::Drop(p) => ActiveFault::Drop(*p),
+            Fault::Duplicate(p) => ActiveFault::Duplicate(*p),
+            Fault::Reorder(max) => ActiveFault::Reorder(*max),
+            Fault::DelaySpike(delay) => ActiveFault::DelaySpike(*delay),
+            Fault::Crash(node_id) => ActiveFault::Crash(node_id.clone().unwrap_or_else(pick)),
+        }
+    }
+
+    fn halves(node_ids: &[NodeId]) -> (Vec<NodeId>, Vec<NodeId>) {
+        let mut shuffled = node_ids.to_vec();
+        for i in (1..shuffled.len()).rev() {
+            let j = runtime::gen_range(0..i as u64 + 1) as usize;
+            shuffled.swap(i, j);
+        }
+        let b = shuffled.split_off(shuffled.len() / 2);
+        (shuffled, b)
+    }
+
+    fn cut(a: &[NodeId], b: &[NodeId]) -> HashSet<(NodeId, NodeId)> {
+        a.iter()
+            .flat_map(|x| {
+                b.iter()
+                    .flat_map(move |y| [(x.clone(), y.clone()), (y.clone(), x.clone())])
+            })
+            .collect()
+    }
+
+    pub fn is_crashed(&self, node_id: &NodeId) -> bool {
+        self.active
+            .iter()
+            .any(|(_, f)| matches!(f, ActiveFault::Crash(x) if x == node_id))
+    }
+
+    /// Decides whether a message between two nodes gets through, how many times and how late.
+    pub fn fate(&self, src: &NodeId, dest: &NodeId) -> Fate {
+        let mut copies = 1;
+        let mut delay = Duration::ZERO;
+        let mut reorder = Duration::ZERO;
+        for (_, fault) in self.active.iter() {
+            match fault {
+                ActiveFault::Partition(pairs) => {
+                    if pairs.contains(&(src.clone(), dest.clone())) {
+                        return Fate::Lost;
+                    }
+                }
+                ActiveFault::Crash(node_id) => {
+                    if node_id == src || node_id == dest {
+                        return Fate::Lost;
+                    }
+                }
+                ActiveFault::Drop(p) => {
+                    if Nemesis::chance(*p) {
+                        return Fate::Lost;
+                    }
+                }
+                ActiveFault::Duplicate(p) => {
+                    if Nemesis::chance(*p) {
+                        copies += 1;
+                    }
+                }
+                ActiveFault::Reorder(max) => reorder = reorder.max(*max),
+                ActiveFault::DelaySpike(extra) => delay += *extra,
+            }
+        }
+        Fate::Delivered(
+            (0..copies)
+                .map(|_| {
+                    let max = reorder.as_millis() as u64;
+                    delay + Duration::from_millis(runtime::gen_range(0..max + 1))
+                })
+                .collect(),
+        )
+    }
+
+    fn chance(p: f64) -> bool {
+        (runtime::gen_range(0..1_000_000) as f64) < p * 1_000_000.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, sim::cluster};
+
+    #[test]
+    fn a_crash_shorter_than_a_step_still_happens() {
+        let mut sim = cluster::start(2, Config::default());
+        let n1 = NodeId::Node(1);
+        cluster::broadcast(&mut sim, &NodeId::Node(0), 1);
+        sim.run_for(cluster::TIMEOUT);
+        assert_eq!(sim.nodes[&n1].delivery_order, [1]);
+        let at = runtime::now() + Duration::from_micros(100);
+        sim.nemesis.add(
+            at,
+            Duration::from_micros(100),
+            Fault::Crash(Some(n1.clone())),
+        );
+        sim.step();
+        assert!(runtime::now() > at + Duration::from_micros(100));
+        assert!(sim.nemesis.active.is_empty());
+        // restarted fresh, so what it had seen is gone
+        assert!(sim.nodes[&n1].delivery_order.is_empty());
+    }
+}
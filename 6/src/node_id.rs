@@ -0,0 +1,115 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// Who a message is from or to. Maelstrom names cluster nodes `n<i>`, clients `c<i>`
+/// and its own services by name (`seq-kv`, `lin-kv`, ...). On the wire it's the plain
+/// string; ordering puts nodes first, by index, so `n2` comes before `n10`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum NodeId {
+    Node(usize),
+    Client(usize),
+    Service(String),
+}
+
+impl NodeId {
+    /// Stands in for a node's own id until `init` says what it is.
+    pub const UNKNOWN: NodeId = NodeId::Service(String::new());
+
+    /// Maelstrom's linearizable key-value service.
+    pub fn lin_kv() -> Self {
+        NodeId::Service(String::from("lin-kv"))
+    }
+
+    pub fn is_node(&self) -> bool {
+        matches!(self, NodeId::Node(_))
+    }
+
+    pub fn is_client(&self) -> bool {
+        matches!(self, NodeId::Client(_))
+    }
+
+    pub fn is_service(&self) -> bool {
+        matches!(self, NodeId::Service(_))
+    }
+
+    /// The number in `n3` or `c3`; services don't have one.
+    pub fn index(&self) -> Option<usize> {
+        match self {
+            NodeId::Node(i) | NodeId::Client(i) => Some(*i),
+            NodeId::Service(_) => None,
+        }
+    }
+}
+
+impl FromStr for NodeId {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let numbered = |prefix: char| {
+            s.strip_prefix(prefix)
+                .filter(|x| !x.is_empty() && x.chars().all(|c| c.is_ascii_digit()))
+                .and_then(|x| x.parse().ok())
+        };
+        if let Some(i) = numbered('n') {
+            Ok(NodeId::Node(i))
+        } else if let Some(i) = numbered('c') {
+            Ok(NodeId::Client(i))
+        } else if !s.is_empty() {
+            Ok(NodeId::Service(s.to_string()))
+        } else {
+            Err(String::from("empty node id"))
+        }
+    }
+}
+
+impl TryFrom<String> for NodeId {
+    type Error = String;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<NodeId> for String {
+    fn from(id: NodeId) -> Self {
+        id.to_string()
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NodeId::Node(i) => write!(f, "n{}", i),
+            NodeId::Client(i) => write!(f, "c{}", i),
+            NodeId::Service(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_orders_like_maelstrom_names() {
+        let ids: Vec<NodeId> = ["n10", "c1", "lin-kv", "n2"]
+            .iter()
+            .map(|x| x.parse().unwrap())
+            .collect();
+        assert_eq!(
+            ids,
+            [
+                NodeId::Node(10),
+                NodeId::Client(1),
+                NodeId::Service(String::from("lin-kv")),
+                NodeId::Node(2),
+            ]
+        );
+        let mut sorted = ids.clone();
+        sorted.sort();
+        let names: Vec<String> = sorted.into_iter().map(String::from).collect();
+        assert_eq!(names, ["n2", "n10", "c1", "lin-kv"]);
+        assert_eq!("n".parse(), Ok(NodeId::Service(String::from("n"))));
+        assert!("".parse::<NodeId>().is_err());
+    }
+}
@@ -0,0 +1,276 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use crate::{
+    history::micros,
+    message::{MaelstromMessage, MaelstromMessageBody, MessageType, MsgIds},
+    node_id::NodeId,
+    runtime,
+};
+
+/// A value announced by `ihave` that hasn't turned up yet.
+#[derive(Serialize, Debug, Clone)]
+pub struct Missing {
+    /// when to graft a link to one of the announcers
+    #[serde(with = "micros")]
+    pub deadline: Duration,
+    pub announcers: Vec<NodeId>,
+    pub grafts: usize,
+}
+
+/// Plumtree epidemic broadcast (Leitão et al.). Values are pushed straight away to the
+/// `eager` peers and only announced to the `lazy` ones, in `ihave` batches each tick.
+/// Everyone starts out eager; whoever sends a value this node already has gets pruned to
+/// lazy, which trims the eager links down to a spanning tree. When an announced value
+/// doesn't arrive in time the link to its announcer is grafted back into the tree.
+///
+/// Pushes and announcements are acked and retransmitted like any broadcast, so a
+/// retransmission from the peer a value first came from isn't taken as a redundant link.
+#[derive(Serialize, Debug, Clone)]
+pub struct Plumtree {
+    pub node_id: NodeId,
+    pub eager: BTreeSet<NodeId>,
+    pub lazy: BTreeSet<NodeId>,
+    /// value -> the peer it first came from (this node, for values from clients)
+    pub received_from: HashMap<usize, NodeId>,
+    /// values waiting to be announced to the lazy peers
+    pub announce: Vec<usize>,
+    pub missing: BTreeMap<usize, Missing>,
+    #[serde(skip)]
+    msg_ids: MsgIds,
+}
+
+impl Plumtree {
+    pub const GRAFT_TIMEOUT_MS: u64 = 100;
+
+    pub fn new(node_id: NodeId, node_ids: &[NodeId], msg_ids: MsgIds) -> Self {
+        Plumtree {
+            eager: node_ids
+                .iter()
+                .filter(|x| **x != node_id)
+                .cloned()
+                .collect(),
+            node_id,
+            lazy: BTreeSet::new(),
+            received_from: HashMap::new(),
+            announce: Vec::new(),
+            missing: BTreeMap::new(),
+            msg_ids,
+        }
+    }
+
+    /// A value from a client, returning whether it's new and what to send.
+    pub fn broadcast(&mut self, message: usize) -> (bool, Vec<MaelstromMessage>) {
+        if self.received_from.contains_key(&message) {
+            return (false, Vec::new());
+        }
+        self.received_from.insert(message, self.node_id.clone());
+        (true, self.push(message, None))
+    }
+
+    /// A value pushed by `src`, returning whether it's new and what to send.
+    pub fn receive(&mut self, message: usize, src: &NodeId) -> (bool, Vec<MaelstromMessage>) {
+        match self.received_from.get(&message) {
+            Some(from) if from == src => (false, Vec::new()),
+            Some(_) => {
+                self.make_lazy(src);
+                let prune = MaelstromMessageBody::new(MessageType::Prune);
+                (false, vec![self.message(src, prune)])
+            }
+            None => {
+                self.received_from.insert(message, src.clone());
+                self.missing.remove(&message);
+                self.make_eager(src);
+                (true, self.push(message, Some(src)))
+            }
+        }
+    }
+
+    pub fn ihave(&mut self, messages: &[usize], src: &NodeId) {
+        let deadline = runtime::now() + Duration::from_millis(Plumtree::GRAFT_TIMEOUT_MS);
+        for message in messages {
+            if self.received_from.contains_key(message) {
+                continue;
+            }
+            let missing = self.missing.entry(*message).or_insert(Missing {
+                deadline,
+                announcers: Vec::new(),
+                grafts: 0,
+            });
+            if !missing.announcers.contains(src) {
+                missing.announcers.push(src.clone());
+            }
+        }
+    }
+
+    /// `src` wants back into the tree, and is missing `messages`.
+    pub fn graft(&mut self, messages: &[usize], src: &NodeId) -> Vec<MaelstromMessage> {
+        self.make_eager(src);
+        messages
+            .iter()
+            .filter(|x| self.received_from.contains_key(x))
+            .map(|x| self.push_to(src, *x))
+            .collect()
+    }
+
+    pub fn prune(&mut self, src: &NodeId) {
+        self.make_lazy(src);
+    }
+
+    /// Announces what's new to the lazy peers, and grafts links for announced values that
+    /// are overdue, trying each announcer in turn.
+    pub fn tick(&mut self) -> Vec<MaelstromMessage> {
+        let mut out = Vec::new();
+        let announce = std::mem::take(&mut self.announce);
+        if !announce.is_empty() {
+            for peer in self.lazy.clone() {
+                let ihave = MaelstromMessageBody {
+                    msg_id: Some(self.msg_ids.next()),
+                    messages: Some(announce.clone()),
+                    ..MaelstromMessageBody::new(MessageType::IHave)
+                };
+                out.push(self.message(&peer, ihave));
+            }
+        }
+        let now = runtime::now();
+        let mut grafts: BTreeMap<NodeId, Vec<usize>> = BTreeMap::new();
+        for (message, missing) in self.missing.iter_mut() {
+            if missing.deadline > now {
+                continue;
+            }
+            let peer = &missing.announcers[missing.grafts % missing.announcers.len()];
+            grafts.entry(peer.clone()).or_default().push(*message);
+            missing.grafts += 1;
+            missing.deadline = now + Duration::from_millis(Plumtree::GRAFT_TIMEOUT_MS);
+        }
+        for (peer, messages) in grafts {
+            self.make_eager(&peer);
+            let graft = MaelstromMessageBody {
+                messages: Some(messages),
+                ..MaelstromMessageBody::new(MessageType::Graft)
+            };
+            out.push(self.message(&peer, graft));
+        }
+        out
+    }
+
+    fn push(&mut self, message: usize, from: Option<&NodeId>) -> Vec<MaelstromMessage> {
+        self.announce.push(message);
+        self.eager
+            .iter()
+            .filter(|x| Some(*x) != from)
+            .map(|x| self.push_to(x, message))
+            .collect()
+    }
+
+    fn push_to(&self, dest: &NodeId, message: usize) -> MaelstromMessage {
+        let body = MaelstromMessageBody {
+            msg_id: Some(self.msg_ids.next()),
+            message: Some(message),
+            ..MaelstromMessageBody::new(MessageType::Broadcast)
+        };
+        self.message(dest, body)
+    }
+
+    fn message(&self, dest: &NodeId, body: MaelstromMessageBody) -> MaelstromMessage {
+        MaelstromMessage {
+            src: self.node_id.clone(),
+            dest: dest.clone(),
+            body,
+        }
+    }
+
+    fn make_eager(&mut self, peer: &NodeId) {
+        self.lazy.remove(peer);
+        self.eager.insert(peer.clone());
+    }
+
+    fn make_lazy(&mut self, peer: &NodeId) {
+        self.eager.remove(peer);
+        self.lazy.insert(peer.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{BroadcastMode, Config},
+        sim::{cluster, Simulator},
+    };
+
+    #[test]
+    fn prunes_redundant_links_and_grafts_them_back() {
+        runtime::install_virtual(0, Duration::ZERO);
+        let (n0, n1, n2) = (NodeId::Node(0), NodeId::Node(1), NodeId::Node(2));
+        let msg_ids = MsgIds::default();
+        let mut node = Plumtree::new(
+            n0.clone(),
+            &[n0.clone(), n1.clone(), n2.clone()],
+            msg_ids.clone(),
+        );
+        let (new, out) = node.receive(1, &n1);
+        assert!(new);
+        assert_eq!(out[0].dest, n2);
+        // the same value again over another link: that link goes lazy
+        let (new, out) = node.receive(1, &n2);
+        assert!(!new);
+        assert_eq!(out[0].body.msg_type, MessageType::Prune);
+        assert!(node.lazy.contains(&n2));
+        // a retransmission over the first link is not redundant
+        assert!(node.receive(1, &n1).1.is_empty());
+        assert!(node.eager.contains(&n1));
+
+        node.ihave(&[2], &n2);
+        let out = node.tick();
+        assert!(out.iter().all(|x| x.body.msg_type == MessageType::IHave));
+        // announced under the node's own ids, after the push to n2
+        assert_eq!(out[0].body.msg_id, Some(msg_ids.last()));
+        assert_eq!(msg_ids.last(), 2);
+        runtime::advance_to(Duration::from_millis(Plumtree::GRAFT_TIMEOUT_MS));
+        let out = node.tick();
+        assert_eq!(out[0].body.msg_type, MessageType::Graft);
+        assert_eq!(out[0].body.messages, Some(vec![2]));
+        assert!(node.eager.contains(&n2));
+        runtime::uninstall_virtual();
+    }
+
+    #[test]
+    fn trims_pushes_down_to_a_tree() {
+        let mut sim = cluster::start(
+            5,
+            Config {
+                broadcast: BroadcastMode::Plumtree,
+                ..Config::default()
+            },
+        );
+        sim.jitter = Duration::from_millis(5);
+        let pushes = |sim: &Simulator| -> u64 {
+            sim.nodes
+                .values()
+                .filter_map(|x| x.metrics.sent.get("broadcast"))
+                .flat_map(|x| x.iter())
+                .filter(|(dest, _)| dest.starts_with('n'))
+                .map(|(_, count)| count)
+                .sum()
+        };
+        let mut before = 0;
+        for message in 0..40 {
+            if message == 20 {
+                before = pushes(&sim);
+            }
+            cluster::broadcast(&mut sim, &NodeId::Node(message % 5), message);
+            sim.run_for(Duration::from_millis(50));
+        }
+        // flooding the full mesh would be 4 pushes per node, 20 per value
+        let per_value = (pushes(&sim) - before) as f64 / 20.0;
+        assert!(per_value < 6.0, "{} pushes per value", per_value);
+        for read in cluster::read_all(&mut sim) {
+            assert_eq!(read.messages.unwrap().len(), 40);
+        }
+    }
+}
@@ -0,0 +1,791 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::Duration,
+};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    message::{MaelstromMessage, MaelstromMessageBody, MessageType, MsgIds},
+    node_id::NodeId,
+    runtime,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogEntry {
+    pub term: usize,
+    pub op: KvOp,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum KvOp {
+    Noop,
+    Read {
+        key: usize,
+    },
+    Write {
+        key: usize,
+        value: usize,
+    },
+    Cas {
+        key: usize,
+        from: usize,
+        to: usize,
+    },
+    /// Not a kv op: a value for total order broadcast, delivered as it's applied.
+    Broadcast {
+        message: usize,
+    },
+}
+
+/// A client request passed on to the leader, answered with an error if the leader
+/// hasn't answered by `deadline`.
+#[derive(Debug, Clone)]
+pub struct Forwarded {
+    pub request: MaelstromMessage,
+    pub deadline: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// Raft replicated lin-kv. Every client op (reads included) goes through the log, and the
+/// leader answers once its entry is applied. Followers proxy client ops to the leader.
+/// Broadcast values can go through the same log for a total order.
+pub struct Raft {
+    pub node_id: NodeId,
+    pub peers: Vec<NodeId>,
+    pub role: Role,
+    pub term: usize,
+    pub voted_for: Option<NodeId>,
+    pub leader_id: Option<NodeId>,
+    /// index 0 is a sentinel so log indexes line up with the paper
+    pub log: Vec<LogEntry>,
+    pub commit_index: usize,
+    pub last_applied: usize,
+    pub votes: HashSet<NodeId>,
+    pub next_index: HashMap<NodeId, usize>,
+    pub match_index: HashMap<NodeId, usize>,
+    pub election_deadline: Duration,
+    pub last_heartbeat: Duration,
+    pub kv: HashMap<usize, usize>,
+    /// broadcast values applied but not yet picked up by the node, in log order
+    pub delivered: Vec<usize>,
+    /// log index -> client (or proxying follower) request waiting on that entry
+    pub pending: BTreeMap<usize, MaelstromMessage>,
+    /// msg_id we forwarded to the leader under -> original client request. Like `pending`,
+    /// ordered so expiring them answers clients in the same order on every run.
+    pub forwarded: BTreeMap<usize, Forwarded>,
+    pub msg_ids: MsgIds,
+}
+
+impl Raft {
+    pub const ELECTION_TIMEOUT_MS: u64 = 150;
+    pub const HEARTBEAT_MS: u128 = 50;
+    pub const FORWARD_TIMEOUT_MS: u64 = 1000;
+
+    pub fn new(node_id: NodeId, node_ids: Vec<NodeId>, msg_ids: MsgIds) -> Self {
+        Raft {
+            peers: node_ids.into_iter().filter(|x| *x != node_id).collect(),
+            node_id,
+            role: Role::Follower,
+            term: 0,
+            voted_for: None,
+            leader_id: None,
+            log: vec![LogEntry {
+                term: 0,
+                op: KvOp::Noop,
+            }],
+            commit_index: 0,
+            last_applied: 0,
+            votes: HashSet::new(),
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            election_deadline: Raft::next_election_deadline(),
+            last_heartbeat: runtime::now(),
+            kv: HashMap::new(),
+            delivered: Vec::new(),
+            pending: BTreeMap::new(),
+            forwarded: BTreeMap::new(),
+            msg_ids,
+        }
+    }
+
+    fn next_election_deadline() -> Duration {
+        let jitter = runtime::gen_range(0..Raft::ELECTION_TIMEOUT_MS);
+        runtime::now() + Duration::from_millis(Raft::ELECTION_TIMEOUT_MS + jitter)
+    }
+
+    fn majority(&self) -> usize {
+        let cluster_size = self.peers.len() + 1;
+        cluster_size / 2 + 1
+    }
+
+    fn last_log_index(&self) -> usize {
+        self.log.len() - 1
+    }
+
+    fn last_log_term(&self) -> usize {
+        self.log.last().unwrap().term
+    }
+
+    fn send(&self, dest: &NodeId, body: MaelstromMessageBody) {
+        MaelstromMessage {
+            src: self.node_id.clone(),
+            dest: dest.clone(),
+            body,
+        }
+        .post();
+    }
+
+    pub fn tick(&mut self) {
+        self.expire_forwarded();
+        match self.role {
+            Role::Leader => {
+                if (runtime::now() - self.last_heartbeat).as_millis() >= Raft::HEARTBEAT_MS {
+                    self.send_append_entries();
+                }
+            }
+            Role::Follower | Role::Candidate => {
+                if runtime::now() >= self.election_deadline {
+                    self.become_candidate();
+                }
+            }
+        }
+    }
+
+    fn become_follower(&mut self, term: usize) {
+        if self.role == Role::Leader {
+            info!("Stepping down in term {}", term);
+            // whatever sits at these indexes once a new leader is done may not be our entry,
+            // but the clients still deserve an answer
+            for (_, request) in std::mem::take(&mut self.pending) {
+                self.give_up(request, "leader stepped down");
+            }
+        }
+        self.role = Role::Follower;
+        if term > self.term {
+            self.term = term;
+            self.voted_for = None;
+            self.leader_id = None;
+        }
+    }
+
+    fn become_candidate(&mut self) {
+        self.role = Role::Candidate;
+        self.term += 1;
+        self.voted_for = Some(self.node_id.clone());
+        self.leader_id = None;
+        self.votes = HashSet::from([self.node_id.clone()]);
+        self.election_deadline = Raft::next_election_deadline();
+        info!("Starting election for term {}", self.term);
+        for peer in self.peers.iter() {
+            self.send(
+                peer,
+                MaelstromMessageBody {
+                    term: Some(self.term),
+                    last_log_index: Some(self.last_log_index()),
+                    last_log_term: Some(self.last_log_term()),
+                    ..MaelstromMessageBody::new(MessageType::RequestVote)
+                },
+            );
+        }
+        self.check_votes();
+    }
+
+    fn check_votes(&mut self) {
+        if self.role == Role::Candidate && self.votes.len() >= self.majority() {
+            self.become_leader();
+        }
+    }
+
+    fn become_leader(&mut self) {
+        info!("Became leader for term {}", self.term);
+        self.role = Role::Leader;
+        self.leader_id = Some(self.node_id.clone());
+        let next = self.last_log_index() + 1;
+        self.next_index = self.peers.iter().map(|x| (x.clone(), next)).collect();
+        self.match_index = self.peers.iter().map(|x| (x.clone(), 0)).collect();
+        // commits anything left over from earlier terms without waiting on a client
+        self.log.push(LogEntry {
+            term: self.term,
+            op: KvOp::Noop,
+        });
+        self.send_append_entries();
+    }
+
+    fn send_append_entries(&mut self) {
+        self.last_heartbeat = runtime::now();
+        for peer in self.peers.iter() {
+            let next = self.next_index[peer];
+            self.send(
+                peer,
+                MaelstromMessageBody {
+                    term: Some(self.term),
+                    prev_log_index: Some(next - 1),
+                    prev_log_term: Some(self.log[next - 1].term),
+                    entries: Some(self.log[next..].to_vec()),
+                    leader_commit: Some(self.commit_index),
+                    ..MaelstromMessageBody::new(MessageType::AppendEntries)
+                },
+            );
+        }
+        self.advance_commit();
+    }
+
+    pub fn handle_request_vote(&mut self, msg: &MaelstromMessage) {
+        let term = msg.body.term.unwrap();
+        if term > self.term {
+            self.become_follower(term);
+        }
+        let up_to_date = (
+            msg.body.last_log_term.unwrap(),
+            msg.body.last_log_index.unwrap(),
+        ) >= (self.last_log_term(), self.last_log_index());
+        let vote_granted = term == self.term
+            && up_to_date
+            && self.voted_for.as_ref().is_none_or(|x| *x == msg.src);
+        if vote_granted {
+            self.voted_for = Some(msg.src.clone());
+            self.election_deadline = Raft::next_election_deadline();
+        }
+        self.send(
+            &msg.src,
+            MaelstromMessageBody {
+                term: Some(self.term),
+                vote_granted: Some(vote_granted),
+                ..msg.body.reply(MessageType::RequestVoteOk)
+            },
+        );
+    }
+
+    pub fn handle_request_vote_ok(&mut self, msg: &MaelstromMessage) {
+        let term = msg.body.term.unwrap();
+        if term > self.term {
+            self.become_follower(term);
+            return;
+        }
+        if term == self.term && msg.body.vote_granted.unwrap() {
+            self.votes.insert(msg.src.clone());
+            self.check_votes();
+        }
+    }
+
+    pub fn handle_append_entries(&mut self, msg: &MaelstromMessage) {
+        let term = msg.body.term.unwrap();
+        let reply = |raft: &Raft, success: bool, match_index: usize| {
+            raft.send(
+                &msg.src,
+                MaelstromMessageBody {
+                    term: Some(raft.term),
+                    success: Some(success),
+                    match_index: Some(match_index),
+                    ..msg.body.reply(MessageType::AppendEntriesOk)
+                },
+            )
+        };
+        if term < self.term {
+            return reply(self, false, 0);
+        }
+        self.become_follower(term);
+        self.leader_id = Some(msg.src.clone());
+        self.election_deadline = Raft::next_election_deadline();
+
+        let prev_log_index = msg.body.prev_log_index.unwrap();
+        if prev_log_index > self.last_log_index()
+            || self.log[prev_log_index].term != msg.body.prev_log_term.unwrap()
+        {
+            // tells the leader how far back to go, rather than one entry per round trip
+            let conflict_term = self.log.get(prev_log_index).map(|x| x.term);
+            let conflict_index = match conflict_term {
+                Some(term) => (1..=prev_log_index)
+                    .find(|i| self.log[*i].term == term)
+                    .unwrap_or(prev_log_index),
+                None => self.log.len(),
+            };
+            return self.send(
+                &msg.src,
+                MaelstromMessageBody {
+                    term: Some(self.term),
+                    success: Some(false),
+                    match_index: Some(0),
+                    conflict_term,
+                    conflict_index: Some(conflict_index),
+                    ..msg.body.reply(MessageType::AppendEntriesOk)
+                },
+            );
+        }
+        let entries = msg.body.entries.clone().unwrap();
+        for (i, entry) in entries.iter().enumerate() {
+            let index = prev_log_index + 1 + i;
+            if index < self.log.len() && self.log[index].term != entry.term {
+                self.log.truncate(index);
+            }
+            if index >= self.log.len() {
+                self.log.push(entry.clone());
+            }
+        }
+        let match_index = prev_log_index + entries.len();
+        let leader_commit = msg.body.leader_commit.unwrap();
+        if leader_commit > self.commit_index {
+            self.commit_index = leader_commit.min(match_index);
+            self.apply();
+        }
+        reply(self, true, match_index);
+    }
+
+    pub fn handle_append_entries_ok(&mut self, msg: &MaelstromMessage) {
+        let term = msg.body.term.unwrap();
+        if term > self.term {
+            self.become_follower(term);
+            return;
+        }
+        if self.role != Role::Leader || term != self.term {
+            return;
+        }
+        if msg.body.success.unwrap() {
+            let match_index = msg.body.match_index.unwrap();
+            let current = self.match_index.get_mut(&msg.src).unwrap();
+            *current = (*current).max(match_index);
+            self.next_index.insert(msg.src.clone(), *current + 1);
+            self.advance_commit();
+        } else {
+            // past the last entry we have from the follower's conflicting term, or to
+            // where it says its log runs out or that term starts
+            let next = match (msg.body.conflict_term, msg.body.conflict_index) {
+                (Some(term), Some(index)) => (1..=self.last_log_index())
+                    .rev()
+                    .find(|i| self.log[*i].term == term)
+                    .map_or(index, |i| i + 1),
+                (None, Some(index)) => index,
+                _ => self.next_index[&msg.src] - 1,
+            };
+            let next = next.clamp(1, self.last_log_index() + 1);
+            self.next_index.insert(msg.src.clone(), next);
+        }
+    }
+
+    /// Moves the commit index up to the newest entry from this term held by a majority.
+    fn advance_commit(&mut self) {
+        for index in (self.commit_index + 1..=self.last_log_index()).rev() {
+            if self.log[index].term != self.term {
+                break;
+            }
+            let replicas = 1 + self.match_index.values().filter(|x| **x >= index).count();
+            if replicas >= self.majority() {
+                self.commit_index = index;
+                break;
+            }
+        }
+        self.apply();
+    }
+
+    fn apply(&mut self) {
+        while self.last_applied < self.commit_index {
+            self.last_applied += 1;
+            let op = self.log[self.last_applied].op.clone();
+            if op == KvOp::Noop {
+                continue;
+            }
+            let result = self.apply_op(&op);
+            if let Some(request) = self.pending.remove(&self.last_applied) {
+                let body = match result {
+                    Ok(body) => body,
+                    Err((code, text)) => request.body.error(code, text),
+                };
+                self.send(
+                    &request.src,
+                    MaelstromMessageBody {
+                        in_reply_to: request.body.msg_id,
+                        ..body
+                    },
+                );
+            }
+        }
+    }
+
+    fn apply_op(&mut self, op: &KvOp) -> Result<MaelstromMessageBody, (usize, &'static str)> {
+        match *op {
+            KvOp::Noop => unreachable!("noops are skipped when applying"),
+            KvOp::Read { key } => match self.kv.get(&key) {
+                Some(value) => Ok(MaelstromMessageBody {
+                    value: Some((*value).into()),
+                    ..MaelstromMessageBody::new(MessageType::ReadOk)
+                }),
+                None => Err((
+                    MaelstromMessageBody::KEY_DOES_NOT_EXIST,
+                    "key does not exist",
+                )),
+            },
+            KvOp::Write { key, value } => {
+                self.kv.insert(key, value);
+                Ok(MaelstromMessageBody::new(MessageType::WriteOk))
+            }
+            KvOp::Cas { key, from, to } => match self.kv.get(&key) {
+                None => Err((
+                    MaelstromMessageBody::KEY_DOES_NOT_EXIST,
+                    "key does not exist",
+                )),
+                Some(value) if *value != from => Err((
+                    MaelstromMessageBody::PRECONDITION_FAILED,
+                    "current value does not match from",
+                )),
+                Some(_) => {
+                    self.kv.insert(key, to);
+                    Ok(MaelstromMessageBody::new(MessageType::CasOk))
+                }
+            },
+            KvOp::Broadcast { message } => {
+                self.delivered.push(message);
+                Ok(MaelstromMessageBody::new(MessageType::BroadcastOk))
+            }
+        }
+    }
+
+    /// A read/write/cas or broadcast from a client, or from a follower proxying one.
+    pub fn client_request(&mut self, msg: MaelstromMessage) {
+        let body = &msg.body;
+        let op = match body.msg_type {
+            MessageType::Read => body.key.map(|key| KvOp::Read { key }),
+            MessageType::Write => body
+                .key
+                .zip(body.value.as_ref().and_then(|x| x.as_u64()))
+                .map(|(key, value)| KvOp::Write {
+                    key,
+                    value: value as usize,
+                }),
+            MessageType::Cas => match (body.key, body.from, body.to) {
+                (Some(key), Some(from), Some(to)) => Some(KvOp::Cas { key, from, to }),
+                _ => None,
+            },
+            MessageType::Broadcast => body.message.map(|message| KvOp::Broadcast { message }),
+            _ => return,
+        };
+        let Some(op) = op else {
+            return self.send(
+                &msg.src,
+                body.error(
+                    MaelstromMessageBody::MALFORMED_REQUEST,
+                    "keys and values must be non-negative integers",
+                ),
+            );
+        };
+        match (self.role, self.leader_id.clone()) {
+            (Role::Leader, _) => {
+                self.log.push(LogEntry {
+                    term: self.term,
+                    op,
+                });
+                self.pending.insert(self.last_log_index(), msg);
+            }
+            (_, Some(leader)) => {
+                let msg_id = self.msg_ids.next();
+                self.send(
+                    &leader,
+                    MaelstromMessageBody {
+                        msg_id: Some(msg_id),
+                        ..msg.body.clone()
+                    },
+                );
+                let deadline = runtime::now() + Duration::from_millis(Raft::FORWARD_TIMEOUT_MS);
+                self.forwarded.insert(
+                    msg_id,
+                    Forwarded {
+                        request: msg,
+                        deadline,
+                    },
+                );
+            }
+            (_, None) => self.send(
+                &msg.src,
+                msg.body
+                    .error(MaelstromMessageBody::TEMPORARILY_UNAVAILABLE, "no leader"),
+            ),
+        }
+    }
+
+    /// Passes the leader's answer to a proxied request back to the client that asked.
+    pub fn relay(&mut self, msg: &MaelstromMessage) {
+        let Some(Forwarded { request, .. }) =
+            msg.body.in_reply_to.and_then(|x| self.forwarded.remove(&x))
+        else {
+            return;
+        };
+        self.send(
+            &request.src,
+            MaelstromMessageBody {
+                in_reply_to: request.body.msg_id,
+                ..msg.body.clone()
+            },
+        );
+    }
+
+    /// Gives up on proxied requests the leader never answered, which happens when it
+    /// crashes or is cut off, so the client isn't left waiting forever.
+    fn expire_forwarded(&mut self) {
+        let now = runtime::now();
+        let expired: Vec<usize> = self
+            .forwarded
+            .iter()
+            .filter(|(_, x)| x.deadline <= now)
+            .map(|(msg_id, _)| *msg_id)
+            .collect();
+        for msg_id in expired {
+            let request = self.forwarded.remove(&msg_id).unwrap().request;
+            self.give_up(request, "no answer from the leader");
+        }
+    }
+
+    /// Answers a request whose fate we can no longer learn. Only a read can be turned away
+    /// outright: anything else may already be in some log and commit later, so it gets the
+    /// indefinite timeout error.
+    fn give_up(&mut self, request: MaelstromMessage, text: &str) {
+        let code = match request.body.msg_type {
+            MessageType::Read => MaelstromMessageBody::TEMPORARILY_UNAVAILABLE,
+            _ => MaelstromMessageBody::TIMEOUT,
+        };
+        self.send(&request.src, request.body.error(code, text));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        nemesis::Fault,
+        sim::cluster::{self, TIMEOUT},
+    };
+
+    fn node_ids() -> Vec<NodeId> {
+        (0..3).map(NodeId::Node).collect()
+    }
+
+    /// Hands each message to the node it's addressed to, returning what they sent back.
+    fn deliver(nodes: &mut [&mut Raft], out: Vec<MaelstromMessage>) -> Vec<MaelstromMessage> {
+        let mut replies = Vec::new();
+        for msg in out {
+            let Some(node) = nodes.iter_mut().find(|x| x.node_id == msg.dest) else {
+                continue;
+            };
+            replies.extend(MaelstromMessage::capture(|| match msg.body.msg_type {
+                MessageType::AppendEntries => node.handle_append_entries(&msg),
+                MessageType::AppendEntriesOk => node.handle_append_entries_ok(&msg),
+                _ => {}
+            }));
+        }
+        replies
+    }
+
+    #[test]
+    fn backs_up_to_where_the_follower_says_its_log_differs() {
+        runtime::install_virtual(0, Duration::ZERO);
+        let mut leader = Raft::new(NodeId::Node(0), node_ids(), MsgIds::default());
+        let mut follower = Raft::new(NodeId::Node(1), node_ids(), MsgIds::default());
+        let entry = |term| LogEntry {
+            term,
+            op: KvOp::Noop,
+        };
+        leader.log.extend([1, 1, 1, 2, 2, 2, 2, 2].map(entry));
+        leader.term = 4;
+        // shorter, and ending in an entry from a term 3 leader that never got it out
+        follower.log.extend([1, 1, 3].map(entry));
+        follower.term = 3;
+        let out = MaelstromMessage::capture(|| leader.become_leader());
+        // too short: try from the end of its log
+        let replies = deliver(&mut [&mut follower], out);
+        assert_eq!(replies[0].body.conflict_index, Some(4));
+        deliver(&mut [&mut leader], replies);
+        assert_eq!(leader.next_index[&NodeId::Node(1)], 4);
+        // then wrong term: skip all of term 3, which the leader has nothing from
+        let out = MaelstromMessage::capture(|| leader.send_append_entries());
+        let replies = deliver(&mut [&mut follower], out);
+        assert_eq!(replies[0].body.conflict_term, Some(3));
+        deliver(&mut [&mut leader], replies);
+        assert_eq!(leader.next_index[&NodeId::Node(1)], 3);
+        let out = MaelstromMessage::capture(|| leader.send_append_entries());
+        let replies = deliver(&mut [&mut follower], out);
+        assert_eq!(replies[0].body.success, Some(true));
+        let terms = |raft: &Raft| raft.log.iter().map(|x| x.term).collect::<Vec<_>>();
+        assert_eq!(terms(&follower), terms(&leader));
+        runtime::uninstall_virtual();
+    }
+
+    #[test]
+    fn rejects_a_write_of_something_other_than_an_integer() {
+        runtime::install_virtual(0, Duration::ZERO);
+        let mut raft = Raft::new(NodeId::Node(0), node_ids(), MsgIds::default());
+        let out = MaelstromMessage::capture(|| {
+            raft.client_request(MaelstromMessage {
+                src: NodeId::Client(1),
+                dest: NodeId::Node(0),
+                body: MaelstromMessageBody {
+                    msg_id: Some(1),
+                    key: Some(1),
+                    value: Some("x".into()),
+                    ..MaelstromMessageBody::new(MessageType::Write)
+                },
+            })
+        });
+        assert_eq!(
+            out[0].body.code,
+            Some(MaelstromMessageBody::MALFORMED_REQUEST)
+        );
+        assert_eq!(out[0].body.in_reply_to, Some(1));
+        assert_eq!(raft.log.len(), 1);
+        runtime::uninstall_virtual();
+    }
+
+    #[test]
+    fn gives_up_on_requests_the_leader_never_answers() {
+        runtime::install_virtual(0, Duration::ZERO);
+        let mut raft = Raft::new(NodeId::Node(1), node_ids(), MsgIds::default());
+        raft.leader_id = Some(NodeId::Node(0));
+        let out = MaelstromMessage::capture(|| {
+            for (msg_id, msg_type) in [(1, MessageType::Read), (2, MessageType::Write)] {
+                raft.client_request(MaelstromMessage {
+                    src: NodeId::Client(1),
+                    dest: NodeId::Node(1),
+                    body: MaelstromMessageBody {
+                        msg_id: Some(msg_id),
+                        key: Some(1),
+                        value: Some(1.into()),
+                        ..MaelstromMessageBody::new(msg_type)
+                    },
+                });
+            }
+        });
+        assert!(out.iter().all(|x| x.dest == NodeId::Node(0)));
+        runtime::advance_to(Duration::from_millis(Raft::FORWARD_TIMEOUT_MS));
+        let out = MaelstromMessage::capture(|| raft.tick());
+        let mut answers: Vec<(Option<usize>, Option<usize>)> = out
+            .iter()
+            .filter(|x| x.dest == NodeId::Client(1))
+            .map(|x| (x.body.in_reply_to, x.body.code))
+            .collect();
+        answers.sort();
+        // the write may still commit, so it can't be turned away outright
+        let expected = [
+            (Some(1), Some(MaelstromMessageBody::TEMPORARILY_UNAVAILABLE)),
+            (Some(2), Some(MaelstromMessageBody::TIMEOUT)),
+        ];
+        assert_eq!(answers, expected);
+        assert!(raft.forwarded.is_empty());
+        runtime::uninstall_virtual();
+    }
+
+    #[test]
+    fn answers_its_clients_when_stepping_down() {
+        runtime::install_virtual(0, Duration::ZERO);
+        let mut raft = Raft::new(NodeId::Node(0), node_ids(), MsgIds::default());
+        MaelstromMessage::capture(|| {
+            raft.become_leader();
+            for (msg_id, msg_type) in [(1, MessageType::Read), (2, MessageType::Write)] {
+                raft.client_request(MaelstromMessage {
+                    src: NodeId::Client(1),
+                    dest: NodeId::Node(0),
+                    body: MaelstromMessageBody {
+                        msg_id: Some(msg_id),
+                        key: Some(1),
+                        value: Some(1.into()),
+                        ..MaelstromMessageBody::new(msg_type)
+                    },
+                });
+            }
+        });
+        let out = MaelstromMessage::capture(|| raft.become_follower(raft.term + 1));
+        let answers: Vec<(Option<usize>, Option<usize>)> = out
+            .iter()
+            .filter(|x| x.dest == NodeId::Client(1))
+            .map(|x| (x.body.in_reply_to, x.body.code))
+            .collect();
+        let expected = [
+            (Some(1), Some(MaelstromMessageBody::TEMPORARILY_UNAVAILABLE)),
+            (Some(2), Some(MaelstromMessageBody::TIMEOUT)),
+        ];
+        assert_eq!(answers, expected);
+        assert!(raft.pending.is_empty());
+        runtime::uninstall_virtual();
+    }
+
+    #[test]
+    fn keeps_serving_through_follower_crash() {
+        let mut sim = cluster::start(
+            5,
+            Config {
+                raft: true,
+                ..Config::default()
+            },
+        );
+        sim.run_for(Duration::from_millis(500));
+        let leader = sim
+            .nodes
+            .iter()
+            .find(|(_, x)| x.raft.as_ref().unwrap().role == Role::Leader)
+            .map(|(id, _)| id.clone())
+            .unwrap();
+        let follower = sim.node_ids().into_iter().find(|x| *x != leader).unwrap();
+        let now = runtime::now();
+        sim.nemesis
+            .add(now, Duration::from_secs(1), Fault::Crash(Some(follower)));
+        sim.run_for(Duration::from_millis(10));
+        let write = sim
+            .call(
+                &leader,
+                MaelstromMessageBody {
+                    key: Some(1),
+                    value: Some(3.into()),
+                    ..MaelstromMessageBody::new(MessageType::Write)
+                },
+                TIMEOUT,
+            )
+            .unwrap();
+        assert_eq!(
+            write.body.msg_type,
+            MessageType::WriteOk,
+            "seed {}",
+            sim.seed
+        );
+    }
+
+    #[test]
+    fn write_then_read_from_another_node() {
+        let mut sim = cluster::start(
+            3,
+            Config {
+                raft: true,
+                ..Config::default()
+            },
+        );
+        sim.run_for(Duration::from_millis(500));
+        let write = sim
+            .call(
+                &NodeId::Node(0),
+                MaelstromMessageBody {
+                    key: Some(1),
+                    value: Some(7.into()),
+                    ..MaelstromMessageBody::new(MessageType::Write)
+                },
+                TIMEOUT,
+            )
+            .unwrap();
+        assert_eq!(write.body.msg_type, MessageType::WriteOk);
+        let read = sim
+            .call(
+                &NodeId::Node(2),
+                MaelstromMessageBody {
+                    key: Some(1),
+                    ..MaelstromMessageBody::new(MessageType::Read)
+                },
+                TIMEOUT,
+            )
+            .unwrap();
+        assert_eq!(read.body.value, Some(7.into()));
+    }
+}
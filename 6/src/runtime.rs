@@ -0,0 +1,98 @@
+use std::{
+    cell::RefCell,
+    ops::Range,
+    sync::OnceLock,
+    time::{Duration, Instant, SystemTime},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use uuid::Uuid;
+
+/// Where nodes get the time and randomness from. By default that's the wall clock and
+/// the thread rng, but a thread can switch to virtual time and a seeded rng (the
+/// simulator does) so a whole run can be replayed from its seed.
+struct Virtual {
+    now: Duration,
+    /// the Unix time virtual time started at
+    epoch: Duration,
+    rng: StdRng,
+}
+
+thread_local! {
+    static VIRTUAL: RefCell<Option<Virtual>> = const { RefCell::new(None) };
+}
+
+static START: OnceLock<Instant> = OnceLock::new();
+
+/// Time since the runtime started, virtual or real.
+pub fn now() -> Duration {
+    VIRTUAL.with(|v| match v.borrow().as_ref() {
+        Some(v) => v.now,
+        None => START.get_or_init(Instant::now).elapsed(),
+    })
+}
+
+/// Time since the Unix epoch, for anything that has to keep moving forward across
+/// restarts. Virtual time counts up from the epoch it was installed with.
+pub fn unix_time() -> Duration {
+    VIRTUAL.with(|v| match v.borrow().as_ref() {
+        Some(v) => v.epoch + v.now,
+        None => SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default(),
+    })
+}
+
+pub fn gen_range(range: Range<u64>) -> u64 {
+    VIRTUAL.with(|v| match v.borrow_mut().as_mut() {
+        Some(v) => v.rng.gen_range(range),
+        None => rand::thread_rng().gen_range(range),
+    })
+}
+
+pub fn uuid() -> Uuid {
+    VIRTUAL.with(|v| match v.borrow_mut().as_mut() {
+        Some(v) => uuid::Builder::from_random_bytes(v.rng.gen()).into_uuid(),
+        None => Uuid::new_v4(),
+    })
+}
+
+/// Switches this thread to virtual time, starting at zero (`epoch` in Unix time), with
+/// an rng seeded from `seed`.
+pub fn install_virtual(seed: u64, epoch: Duration) {
+    VIRTUAL.with(|v| {
+        *v.borrow_mut() = Some(Virtual {
+            now: Duration::ZERO,
+            epoch,
+            rng: StdRng::seed_from_u64(seed),
+        })
+    });
+}
+
+pub fn uninstall_virtual() {
+    VIRTUAL.with(|v| *v.borrow_mut() = None);
+}
+
+/// Waits until the Unix time is `to`, sleeping on the wall clock and jumping virtual
+/// time straight there.
+pub fn wait_until_unix(to: Duration) {
+    let virtual_time = VIRTUAL.with(|v| match v.borrow_mut().as_mut() {
+        Some(v) => {
+            v.now = v.now.max(to.saturating_sub(v.epoch));
+            true
+        }
+        None => false,
+    });
+    while !virtual_time && unix_time() < to {
+        std::thread::sleep(to.saturating_sub(unix_time()));
+    }
+}
+
+/// Moves virtual time forward to `to`. Does nothing on the wall clock or if `to` is past.
+pub fn advance_to(to: Duration) {
+    VIRTUAL.with(|v| {
+        if let Some(v) = v.borrow_mut().as_mut() {
+            v.now = v.now.max(to);
+        }
+    });
+}
@@ -0,0 +1,524 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use crate::{
+    config::Config,
+    history::History,
+    message::{MaelstromMessage, MaelstromMessageBody, MessageType},
+    nemesis::{ActiveFault, Fate, Nemesis},
+    node_id::NodeId,
+    runtime,
+    state::State,
+};
+
+/// Runs a cluster of nodes in one process, standing in for Maelstrom's network.
+/// Messages between nodes are delivered after `latency` plus up to `jitter`, as are
+/// requests to `lin-kv` and its replies, answered from `lin_kv`. Anything addressed
+/// elsewhere is a reply to a client and ends up in `replies`.
+///
+/// Faults are injected on a schedule through `nemesis`.
+///
+/// Time is virtual and every random choice (the nodes' included) comes from `seed`, so
+/// a run can be repeated exactly by building the simulator with the same seed.
+pub struct Simulator {
+    pub seed: u64,
+    pub nodes: BTreeMap<NodeId, State>,
+    pub latency: Duration,
+    pub jitter: Duration,
+    pub replies: Vec<MaelstromMessage>,
+    /// what the `lin-kv` service holds
+    pub lin_kv: BTreeMap<usize, usize>,
+    /// every client request and reply, for the checkers
+    pub history: History,
+    pub nemesis: Nemesis,
+    config: Config,
+    /// (deliver at, send order) -> message
+    in_flight: BTreeMap<(Duration, usize), MaelstromMessage>,
+    sent: usize,
+    next_msg_id: usize,
+    next_tick: Duration,
+}
+
+impl Simulator {
+    pub const CLIENT: NodeId = NodeId::Client(1);
+
+    /// Picks a fresh seed, kept in `seed` so a failing run can be repeated with
+    /// [`Simulator::with_seed`].
+    pub fn new(node_count: usize, config: Config) -> Self {
+        Simulator::with_seed(node_count, config, rand::random())
+    }
+
+    /// Puts this thread on virtual time seeded with `seed` until the simulator is dropped.
+    pub fn with_seed(node_count: usize, config: Config, seed: u64) -> Self {
+        runtime::install_virtual(seed, Duration::ZERO);
+        Simulator {
+            seed,
+            nodes: (0..node_count)
+                .map(|i| (NodeId::Node(i), State::new(config.clone())))
+                .collect(),
+            config,
+            nemesis: Nemesis::default(),
+            latency: Duration::from_millis(5),
+            jitter: Duration::ZERO,
+            replies: Vec::new(),
+            lin_kv: BTreeMap::new(),
+            history: History::default(),
+            in_flight: BTreeMap::new(),
+            sent: 0,
+            next_msg_id: 0,
+            next_tick: Duration::ZERO,
+        }
+    }
+
+    pub fn node_ids(&self) -> Vec<NodeId> {
+        self.nodes.keys().cloned().collect()
+    }
+
+    /// Sends `init` to every node, followed by a topology where everyone neighbours everyone.
+    pub fn init(&mut self) {
+        for node_id in self.node_ids() {
+            self.init_node(&node_id);
+        }
+    }
+
+    fn init_node(&mut self, node_id: &NodeId) {
+        let node_ids = self.node_ids();
+        self.request(
+            node_id,
+            MaelstromMessageBody {
+                node_id: Some(node_id.clone()),
+                node_ids: Some(node_ids.clone()),
+                ..MaelstromMessageBody::new(MessageType::Init)
+            },
+        );
+        let topology: BTreeMap<NodeId, Vec<NodeId>> = node_ids
+            .iter()
+            .map(|x| {
+                let others = node_ids.iter().filter(|y| *y != x).cloned().collect();
+                (x.clone(), others)
+            })
+            .collect();
+        self.request(
+            node_id,
+            MaelstromMessageBody {
+                topology: Some(topology),
+                ..MaelstromMessageBody::new(MessageType::Topology)
+            },
+        );
+    }
+
+    /// Hands a client request straight to `node_id`, returning the msg_id it went out with.
+    pub fn request(&mut self, node_id: &NodeId, body: MaelstromMessageBody) -> usize {
+        let msg_id = self.next_msg_id;
+        self.next_msg_id += 1;
+        let msg = MaelstromMessage {
+            src: Simulator::CLIENT,
+            dest: node_id.clone(),
+            body: MaelstromMessageBody {
+                msg_id: Some(msg_id),
+                ..body
+            },
+        };
+        self.history.push(runtime::now(), msg.clone());
+        self.deliver(msg);
+        msg_id
+    }
+
+    pub fn reply_to(&self, msg_id: usize) -> Option<&MaelstromMessage> {
+        self.replies
+            .iter()
+            .find(|x| x.body.in_reply_to == Some(msg_id))
+    }
+
+    /// Sends a request and runs the cluster until it is answered or `timeout` passes.
+    pub fn call(
+        &mut self,
+        node_id: &NodeId,
+        body: MaelstromMessageBody,
+        timeout: Duration,
+    ) -> Option<MaelstromMessage> {
+        let msg_id = self.request(node_id, body);
+        let end = runtime::now() + timeout;
+        while self.next_event() <= end {
+            if self.reply_to(msg_id).is_some() {
+                break;
+            }
+            self.step();
+        }
+        self.reply_to(msg_id).cloned()
+    }
+
+    pub fn run_for(&mut self, duration: Duration) {
+        let end = runtime::now() + duration;
+        while self.next_event() <= end {
+            self.step();
+        }
+        runtime::advance_to(end);
+    }
+
+    /// When the next delivery or node tick is due.
+    fn next_event(&self) -> Duration {
+        match self.in_flight.keys().next() {
+            Some((deliver_at, _)) => (*deliver_at).min(self.next_tick),
+            None => self.next_tick,
+        }
+    }
+
+    /// Jumps to the next event and runs it: a delivery, or every node's tick.
+    pub fn step(&mut self) {
+        runtime::advance_to(self.next_event());
+        for healed in self.nemesis.update(&self.node_ids()) {
+            if let ActiveFault::Crash(node_id) = healed {
+                self.nodes
+                    .insert(node_id.clone(), State::new(self.config.clone()));
+                self.init_node(&node_id);
+            }
+        }
+        if let Some(entry) = self.in_flight.first_entry() {
+            if entry.key().0 <= runtime::now() {
+                let msg = entry.remove();
+                self.deliver(msg);
+                return;
+            }
+        }
+        self.next_tick = runtime::now() + Duration::from_millis(State::LOOP_DELAY_MS);
+        for node_id in self.node_ids() {
+            if self.nemesis.is_crashed(&node_id) {
+                continue;
+            }
+            let node = self.nodes.get_mut(&node_id).unwrap();
+            let out = MaelstromMessage::capture(|| node.tick());
+            self.route(out);
+        }
+    }
+
+    fn deliver(&mut self, msg: MaelstromMessage) {
+        if msg.dest == NodeId::lin_kv() {
+            let reply = self.serve_lin_kv(msg);
+            self.route(vec![reply]);
+            return;
+        }
+        if self.nemesis.is_crashed(&msg.dest) {
+            return;
+        }
+        let Some(node) = self.nodes.get_mut(&msg.dest) else {
+            return;
+        };
+        let out = MaelstromMessage::capture(|| node.handle(msg));
+        self.route(out);
+    }
+
+    fn route(&mut self, out: Vec<MaelstromMessage>) {
+        for msg in out {
+            if !self.nodes.contains_key(&msg.dest) && msg.dest != NodeId::lin_kv() {
+                self.history.push(runtime::now(), msg.clone());
+                self.replies.push(msg);
+                continue;
+            }
+            let Fate::Delivered(delays) = self.nemesis.fate(&msg.src, &msg.dest) else {
+                continue;
+            };
+            for delay in delays {
+                let jitter = match self.jitter.as_millis() as u64 {
+                    0 => 0,
+                    max => runtime::gen_range(0..max + 1),
+                };
+                let deliver_at =
+                    runtime::now() + self.latency + delay + Duration::from_millis(jitter);
+                self.in_flight.insert((deliver_at, self.sent), msg.clone());
+                self.sent += 1;
+            }
+        }
+    }
+
+    /// Maelstrom's `lin-kv`, which being one map applied to in delivery order is
+    /// trivially linearizable.
+    fn serve_lin_kv(&mut self, msg: MaelstromMessage) -> MaelstromMessage {
+        let key = msg.body.key.unwrap_or_default();
+        let current = self.lin_kv.get(&key).copied();
+        let body = match (&msg.body.msg_type, current) {
+            (MessageType::Read, Some(value)) => MaelstromMessageBody {
+                value: Some(value.into()),
+                ..msg.body.reply(MessageType::ReadOk)
+            },
+            (MessageType::Write, _) => {
+                self.lin_kv.insert(
+                    key,
+                    msg.body
+                        .value
+                        .as_ref()
+                        .and_then(|x| x.as_u64())
+                        .unwrap_or_default() as usize,
+                );
+                msg.body.reply(MessageType::WriteOk)
+            }
+            (MessageType::Cas, None) if msg.body.create_if_not_exists == Some(true) => {
+                self.lin_kv.insert(key, msg.body.to.unwrap());
+                msg.body.reply(MessageType::CasOk)
+            }
+            (MessageType::Cas, Some(value)) if Some(value) == msg.body.from => {
+                self.lin_kv.insert(key, msg.body.to.unwrap());
+                msg.body.reply(MessageType::CasOk)
+            }
+            (MessageType::Cas, Some(value)) => msg.body.error(
+                MaelstromMessageBody::PRECONDITION_FAILED,
+                &format!("expected {:?}, had {}", msg.body.from, value),
+            ),
+            (MessageType::Read | MessageType::Cas, None) => msg.body.error(
+                MaelstromMessageBody::KEY_DOES_NOT_EXIST,
+                "key does not exist",
+            ),
+            _ => msg.body.error(
+                MaelstromMessageBody::NOT_SUPPORTED,
+                "not a lin-kv operation",
+            ),
+        };
+        MaelstromMessage {
+            src: msg.dest,
+            dest: msg.src,
+            body,
+        }
+    }
+}
+
+impl Drop for Simulator {
+    fn drop(&mut self) {
+        runtime::uninstall_virtual();
+    }
+}
+
+/// What the tests that run a cluster share, here and in the modules making up a node.
+#[cfg(test)]
+pub(crate) mod cluster {
+    use super::*;
+
+    /// How long a test waits on any one reply.
+    pub const TIMEOUT: Duration = Duration::from_secs(2);
+
+    /// `node_count` nodes running `config`, already inited.
+    pub fn start(node_count: usize, config: Config) -> Simulator {
+        let mut sim = Simulator::new(node_count, config);
+        sim.init();
+        sim
+    }
+
+    /// Has a client broadcast `message` through `node_id`, not waiting on the reply.
+    pub fn broadcast(sim: &mut Simulator, node_id: &NodeId, message: usize) {
+        sim.request(
+            node_id,
+            MaelstromMessageBody {
+                message: Some(message),
+                ..MaelstromMessageBody::new(MessageType::Broadcast)
+            },
+        );
+    }
+
+    /// What each node answers a plain `read` with, in node order.
+    pub fn read_all(sim: &mut Simulator) -> Vec<MaelstromMessageBody> {
+        sim.node_ids()
+            .iter()
+            .map(|x| {
+                let read = MaelstromMessageBody::new(MessageType::Read);
+                sim.call(x, read, TIMEOUT).unwrap().body
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cluster, *};
+    use crate::{
+        checker::{broadcast, linearizable, txn},
+        config::{BroadcastMode, Isolation},
+        message::MicroOp,
+        nemesis::Fault,
+    };
+
+    #[test]
+    fn broadcast_reaches_every_node() {
+        let mut sim = cluster::start(5, Config::default());
+        for message in 0..10 {
+            cluster::broadcast(&mut sim, &NodeId::Node(0), message);
+        }
+        sim.run_for(Duration::from_millis(200));
+        for (node_id, read) in sim.node_ids().iter().zip(cluster::read_all(&mut sim)) {
+            let mut messages = read.messages.unwrap();
+            messages.sort();
+            assert_eq!(messages, (0..10).collect::<Vec<usize>>(), "{}", node_id);
+        }
+    }
+
+    #[test]
+    fn broadcast_survives_nemesis() {
+        broadcast_survives_nemesis_in(BroadcastMode::BestEffort);
+    }
+
+    #[test]
+    fn plumtree_survives_nemesis() {
+        broadcast_survives_nemesis_in(BroadcastMode::Plumtree);
+    }
+
+    fn broadcast_survives_nemesis_in(mode: BroadcastMode) {
+        let mut sim = Simulator::new(
+            5,
+            Config {
+                broadcast: mode,
+                ..Config::default()
+            },
+        );
+        let second = Duration::from_secs(1);
+        sim.nemesis
+            .every(second, second / 2, second * 6, Fault::PartitionHalves);
+        sim.nemesis.add(second, second, Fault::Bridge);
+        sim.nemesis.add(second * 2, second, Fault::Isolate(None));
+        sim.nemesis
+            .add(Duration::ZERO, second * 6, Fault::Drop(0.2));
+        sim.nemesis
+            .add(Duration::ZERO, second * 6, Fault::Duplicate(0.2));
+        sim.nemesis
+            .add(Duration::ZERO, second * 6, Fault::Reorder(second / 20));
+        sim.nemesis
+            .add(second * 4, second / 2, Fault::DelaySpike(second / 5));
+        sim.init();
+        for message in 0..50 {
+            cluster::broadcast(&mut sim, &NodeId::Node(message % 5), message);
+            sim.run_for(second / 10);
+        }
+        sim.run_for(second * 2);
+        cluster::read_all(&mut sim);
+        let report = broadcast::check(&sim.history);
+        assert!(report.valid(), "{:?} seed {}", report, sim.seed);
+        assert_eq!(report.stable_count, 50, "seed {}", sim.seed);
+    }
+
+    /// Same seed, same everything: what each client got back and every message sent.
+    #[test]
+    fn same_seed_same_run() {
+        let run = |seed| {
+            let mut sim = Simulator::with_seed(
+                3,
+                Config {
+                    raft: true,
+                    ..Config::default()
+                },
+                seed,
+            );
+            sim.jitter = Duration::from_millis(20);
+            sim.init();
+            // let an election settle first, before that writes are turned away
+            sim.run_for(Duration::from_millis(500));
+            for key in 0..5 {
+                sim.request(
+                    &NodeId::Node(key % 3),
+                    MaelstromMessageBody {
+                        key: Some(key),
+                        value: Some(key.into()),
+                        ..MaelstromMessageBody::new(MessageType::Write)
+                    },
+                );
+                sim.run_for(Duration::from_millis(100));
+            }
+            sim.run_for(Duration::from_secs(1));
+            let written = sim
+                .replies
+                .iter()
+                .filter(|x| x.body.msg_type == MessageType::WriteOk)
+                .count();
+            assert_eq!(written, 5, "seed {}", seed);
+            (serde_json::to_string(&sim.replies).unwrap(), sim.sent)
+        };
+        assert_eq!(run(42), run(42));
+    }
+
+    #[test]
+    fn lin_kv_is_linearizable_under_partitions() {
+        let mut sim = Simulator::new(
+            5,
+            Config {
+                raft: true,
+                ..Config::default()
+            },
+        );
+        let second = Duration::from_secs(1);
+        sim.nemesis
+            .every(second, second / 2, second * 5, Fault::PartitionHalves);
+        sim.nemesis
+            .add(Duration::ZERO, second * 5, Fault::Reorder(second / 50));
+        sim.init();
+        for i in 0..200 {
+            let key = runtime::gen_range(0..3) as usize;
+            let value = runtime::gen_range(0..5) as usize;
+            let body = match runtime::gen_range(0..3) {
+                0 => MaelstromMessageBody {
+                    key: Some(key),
+                    ..MaelstromMessageBody::new(MessageType::Read)
+                },
+                1 => MaelstromMessageBody {
+                    key: Some(key),
+                    value: Some(value.into()),
+                    ..MaelstromMessageBody::new(MessageType::Write)
+                },
+                _ => MaelstromMessageBody {
+                    key: Some(key),
+                    from: Some(value),
+                    to: Some(runtime::gen_range(0..5) as usize),
+                    ..MaelstromMessageBody::new(MessageType::Cas)
+                },
+            };
+            sim.request(&NodeId::Node(i % 5), body);
+            sim.run_for(second / 40);
+        }
+        sim.run_for(second);
+        let report = linearizable::check(&sim.history);
+        assert!(report.valid(), "{:?} seed {}", report, sim.seed);
+    }
+
+    #[test]
+    fn read_committed_txns_have_no_g1() {
+        let mut sim = Simulator::new(
+            3,
+            Config {
+                isolation: Isolation::ReadCommitted,
+                ..Config::default()
+            },
+        );
+        let second = Duration::from_secs(1);
+        sim.nemesis
+            .add(Duration::ZERO, second * 3, Fault::Reorder(second / 20));
+        sim.nemesis
+            .add(Duration::ZERO, second * 3, Fault::Drop(0.1));
+        sim.init();
+        // every write to a key gets a fresh value so reads point back at one writer
+        let mut next_value = 0;
+        for i in 0..100 {
+            let txn = (0..runtime::gen_range(1..5))
+                .map(|_| {
+                    let key = runtime::gen_range(0..4) as usize;
+                    if runtime::gen_range(0..2) == 0 {
+                        MicroOp::Read(key, None)
+                    } else {
+                        next_value += 1;
+                        MicroOp::Write(key, next_value)
+                    }
+                })
+                .collect();
+            sim.request(
+                &NodeId::Node(i % 3),
+                MaelstromMessageBody {
+                    txn: Some(txn),
+                    ..MaelstromMessageBody::new(MessageType::Txn)
+                },
+            );
+            sim.run_for(second / 40);
+        }
+        sim.run_for(second);
+        let report = txn::check(&sim.history);
+        assert_eq!(report.committed_count, 100, "seed {}", sim.seed);
+        assert!(
+            report.satisfies(Isolation::ReadCommitted),
+            "{:?} seed {}",
+            report,
+            sim.seed
+        );
+    }
+}
@@ -0,0 +1,925 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    time::Duration,
+};
+
+use log::info;
+use serde_json::{json, Value};
+
+use crate::{
+    causal::CausalBroadcast,
+    clocks::Clocks,
+    config::{BroadcastMode, Config, Isolation},
+    crdt::{CrdtNode, GSet, LWWRegister},
+    election::Election,
+    failure_detector::PhiAccrual,
+    ids::IdGenerator,
+    membership::{Status, Swim},
+    message::{MaelstromMessage, MaelstromMessageBody, MessageType, MicroOp, MsgIds},
+    metrics::Metrics,
+    node_id::NodeId,
+    plumtree::Plumtree,
+    raft::Raft,
+    runtime, setup_logging,
+    total_order::TotalOrder,
+};
+
+pub struct State {
+    pub config: Config,
+    pub node_id: NodeId,
+    pub node_ids: Vec<NodeId>,
+    /// every broadcast value delivered here
+    pub seen_messages: GSet<usize>,
+    /// the same values in the order they were delivered, which is what `read` answers
+    /// with, since total order and causal broadcast make promises about it
+    pub delivery_order: Vec<usize>,
+    pub topology: BTreeMap<NodeId, Vec<NodeId>>,
+    pub awaiting_ack: Vec<PostAck>,
+    /// messages that were awaiting an ack from a peer since declared dead, sent again
+    /// once it's reachable. Ordered, so unparking sends in the same order on every run.
+    pub parked: BTreeMap<NodeId, VecDeque<MaelstromMessage>>,
+    pub store: HashMap<usize, LWWRegister<usize>>,
+    pub clock: usize,
+    pub msg_ids: MsgIds,
+    pub clocks: Clocks,
+    pub ids: IdGenerator,
+    pub raft: Option<Raft>,
+    pub crdt: Option<CrdtNode>,
+    pub causal: Option<CausalBroadcast>,
+    pub total_order: Option<TotalOrder>,
+    pub plumtree: Option<Plumtree>,
+    pub membership: Option<Swim>,
+    pub failure_detector: Option<PhiAccrual>,
+    pub election: Option<Election>,
+    pub metrics: Metrics,
+}
+
+impl State {
+    pub const LOOP_DELAY_MS: u64 = 10;
+    /// most messages parked per dead peer, past which the oldest are dropped
+    pub const MAX_PARKED: usize = 1000;
+    pub fn new(config: Config) -> Self {
+        State {
+            ids: IdGenerator::new(config.ids),
+            clocks: Clocks::new(config.clocks.clone()),
+            config,
+            awaiting_ack: Vec::new(),
+            parked: BTreeMap::new(),
+            node_id: NodeId::UNKNOWN,
+            node_ids: Vec::new(),
+            seen_messages: GSet::default(),
+            delivery_order: Vec::new(),
+            topology: BTreeMap::new(),
+            store: HashMap::new(),
+            clock: 0,
+            msg_ids: MsgIds::default(),
+            raft: None,
+            crdt: None,
+            causal: None,
+            total_order: None,
+            plumtree: None,
+            membership: None,
+            failure_detector: None,
+            election: None,
+            metrics: Metrics::default(),
+        }
+    }
+    pub fn add_to_ack(&mut self, msg: MaelstromMessage) {
+        self.awaiting_ack.push(PostAck {
+            timestamp: runtime::now(),
+            msg,
+        });
+    }
+    pub fn try_remove_ack(&mut self, msg: &MaelstromMessage) {
+        info!("OK Recieved: {:?} from {}", msg.body.in_reply_to, msg.src);
+        let acked = |x: &MaelstromMessage| {
+            x.dest == msg.src
+                && x.body.msg_id.is_some()
+                && x.body.msg_id == msg.body.in_reply_to
+                && Some(&x.body.msg_type) == msg.body.msg_type.acks().as_ref()
+        };
+        if let Some(i) = self.awaiting_ack.iter().position(|x| acked(&x.msg)) {
+            self.awaiting_ack.remove(i);
+            return;
+        }
+        // a late ack for something parked since
+        if let Some(parked) = self.parked.get_mut(&msg.src) {
+            parked.retain(|x| !acked(x));
+        }
+    }
+
+    pub fn post_ack(&mut self, msg: MaelstromMessage) {
+        self.add_to_ack(msg.clone());
+        info!("PostAck: {}", msg);
+        msg.post();
+    }
+    pub fn tick(&mut self) {
+        let start = runtime::now();
+        let out = MaelstromMessage::capture(|| self.on_tick());
+        self.metrics.handled("tick", runtime::now() - start);
+        self.send(out);
+    }
+    fn on_tick(&mut self) {
+        self.repost_on_late();
+        if let Some(raft) = &mut self.raft {
+            raft.tick();
+        }
+        if let Some(total_order) = &mut self.total_order {
+            let (delivered, out) = total_order.tick();
+            for message in delivered {
+                self.see(message);
+            }
+            out.into_iter().for_each(|x| self.post_ack(x));
+        }
+        if let Some(swim) = &mut self.membership {
+            swim.tick().into_iter().for_each(|x| x.post());
+        }
+        if let Some(detector) = &self.failure_detector {
+            for peer in detector.quiet_peers() {
+                MaelstromMessage {
+                    src: self.node_id.clone(),
+                    dest: peer,
+                    body: MaelstromMessageBody::new(MessageType::Heartbeat),
+                }
+                .post();
+            }
+        }
+        if let Some(plumtree) = &mut self.plumtree {
+            let out = plumtree.tick();
+            self.send_plumtree(out);
+        }
+        let unreachable = self.unreachable_peers();
+        if let Some(election) = &mut self.election {
+            let out = election.tick(&unreachable);
+            out.into_iter().for_each(|x| x.post());
+        }
+        self.take_raft_broadcasts();
+        self.take_leader_changes();
+        self.gossip_crdt();
+    }
+    /// Pushes and announcements are retransmitted until acked; grafts and prunes are
+    /// just sent, a lost graft is retried on the next timeout and a lost prune repeated on
+    /// the next redundant push.
+    fn send_plumtree(&mut self, out: Vec<MaelstromMessage>) {
+        for msg in out {
+            match msg.body.msg_type {
+                MessageType::Broadcast | MessageType::IHave => self.post_ack(msg),
+                _ => msg.post(),
+            }
+        }
+    }
+    /// Moves broadcast values the raft log has applied into view, in log order.
+    fn take_raft_broadcasts(&mut self) {
+        if let Some(raft) = &mut self.raft {
+            for message in std::mem::take(&mut raft.delivered) {
+                self.see(message);
+            }
+        }
+    }
+    /// Logs each change of leader the election has seen since last time.
+    fn take_leader_changes(&mut self) {
+        let Some(election) = &mut self.election else {
+            return;
+        };
+        for change in election.take_changes() {
+            match &change.leader {
+                Some(leader) => info!("Leader: {} with token {}", leader, change.token),
+                None => info!("Leader: none, last token {}", change.token),
+            }
+            self.metrics.leader_changes += 1;
+        }
+    }
+    /// Peers either failure detector has given up on, for the election not to wait on.
+    fn unreachable_peers(&self) -> Vec<NodeId> {
+        self.node_ids
+            .iter()
+            .filter(|x| **x != self.node_id && (!self.is_reachable(x) || self.is_suspected(x)))
+            .cloned()
+            .collect()
+    }
+    /// False for peers a failure detector has given up on: declared dead by SWIM, or
+    /// silent too long under phi-accrual. True without either.
+    pub fn is_reachable(&self, node_id: &NodeId) -> bool {
+        self.membership
+            .as_ref()
+            .is_none_or(|x| x.is_reachable(node_id))
+            && self
+                .failure_detector
+                .as_ref()
+                .is_none_or(|x| !x.is_dead(node_id))
+    }
+    /// Whether the phi-accrual detector suspects `node_id`. Never, without one.
+    pub fn is_suspected(&self, node_id: &NodeId) -> bool {
+        self.failure_detector
+            .as_ref()
+            .is_some_and(|x| x.is_suspected(node_id))
+    }
+    /// How long to wait for an ack from `dest` before sending again, longer for suspected
+    /// peers.
+    fn repost_delay(&self, dest: &NodeId) -> u128 {
+        if self.is_suspected(dest) {
+            MaelstromMessage::REPOST_DELAY_MS * PhiAccrual::BACKOFF
+        } else {
+            MaelstromMessage::REPOST_DELAY_MS
+        }
+    }
+    pub fn gossip_crdt(&mut self) {
+        let Some(payload) = self.crdt.as_mut().and_then(|x| x.tick()) else {
+            return;
+        };
+        self.node_ids
+            .iter()
+            .filter(|x| **x != self.node_id && self.is_reachable(x))
+            .for_each(|dst_node| {
+                MaelstromMessage {
+                    src: self.node_id.clone(),
+                    dest: dst_node.clone(),
+                    body: MaelstromMessageBody {
+                        crdt: Some(payload.clone()),
+                        ..MaelstromMessageBody::new(MessageType::Gossip)
+                    },
+                }
+                .post()
+            });
+    }
+    /// Resends whatever has waited too long for an ack. What's waiting on a dead peer is
+    /// parked instead of sent into the void, and goes out again once the peer is back.
+    pub fn repost_on_late(&mut self) {
+        self.unpark();
+        let now = runtime::now();
+        let mut need_to_repost: Vec<PostAck> = Vec::new();
+        for x in std::mem::take(&mut self.awaiting_ack) {
+            if !self.is_reachable(&x.msg.dest) {
+                self.park(x.msg);
+            } else if (now - x.timestamp).as_millis() < self.repost_delay(&x.msg.dest) {
+                self.awaiting_ack.push(x);
+            } else {
+                need_to_repost.push(x);
+            }
+        }
+        need_to_repost.iter().for_each(|x| {
+            info!(
+                "Reposting: {} to {}",
+                &x.msg.body.msg_id.unwrap(),
+                &x.msg.dest
+            )
+        });
+        self.metrics.retransmissions += need_to_repost.len() as u64;
+        need_to_repost
+            .into_iter()
+            .for_each(|x| self.post_ack(x.msg));
+    }
+
+    /// Sets a message for a dead peer aside, dropping the oldest past [`State::MAX_PARKED`].
+    fn park(&mut self, msg: MaelstromMessage) {
+        let parked = self.parked.entry(msg.dest.clone()).or_default();
+        if parked.len() == State::MAX_PARKED {
+            if let Some(dropped) = parked.pop_front() {
+                info!("Dropping parked: {}", dropped);
+            }
+        }
+        parked.push_back(msg);
+    }
+    /// Sends everything parked for peers that are reachable again.
+    fn unpark(&mut self) {
+        let back: Vec<NodeId> = self
+            .parked
+            .keys()
+            .filter(|x| self.is_reachable(x))
+            .cloned()
+            .collect();
+        for node_id in back {
+            let parked = self.parked.remove(&node_id).unwrap();
+            info!("Unparking {} messages to {}", parked.len(), node_id);
+            parked.into_iter().for_each(|x| self.post_ack(x));
+        }
+    }
+
+    /// Runs the ops against the local store in order, filling in the value for each read.
+    /// Writes only land if they are newer than what is stored (last-writer-wins on
+    /// `(timestamp, node_id)`), so every node settles on the same version of each key.
+    pub fn apply_txn(
+        &mut self,
+        txn: Vec<MicroOp>,
+        timestamp: usize,
+        node_id: &NodeId,
+    ) -> Vec<MicroOp> {
+        txn.into_iter()
+            .map(|op| match op {
+                MicroOp::Read(key, _) => {
+                    MicroOp::Read(key, self.store.get(&key).and_then(|x| x.get().copied()))
+                }
+                MicroOp::Write(key, value) => {
+                    self.store
+                        .entry(key)
+                        .or_default()
+                        .set(value, timestamp, &node_id.to_string());
+                    op
+                }
+            })
+            .collect()
+    }
+
+    /// Runs the ops against a write buffer on top of the store without touching it.
+    /// Returns the ops with reads filled in and the final write for each key, which is
+    /// all that should ever be committed or replicated.
+    pub fn buffer_txn(&self, txn: Vec<MicroOp>) -> (Vec<MicroOp>, Vec<MicroOp>) {
+        let mut buffer: Vec<(usize, usize)> = Vec::new();
+        let txn = txn
+            .into_iter()
+            .map(|op| match op {
+                MicroOp::Read(key, _) => {
+                    let buffered = buffer.iter().find(|(k, _)| *k == key).map(|(_, v)| *v);
+                    let stored = self.store.get(&key).and_then(|x| x.get().copied());
+                    MicroOp::Read(key, buffered.or(stored))
+                }
+                MicroOp::Write(key, value) => {
+                    match buffer.iter_mut().find(|(k, _)| *k == key) {
+                        Some(buffered) => buffered.1 = value,
+                        None => buffer.push((key, value)),
+                    }
+                    op
+                }
+            })
+            .collect();
+        let writes = buffer
+            .into_iter()
+            .map(|(key, value)| MicroOp::Write(key, value))
+            .collect();
+        (txn, writes)
+    }
+
+    pub fn replicate_txn(&mut self, writes: Vec<MicroOp>, timestamp: usize) {
+        if writes.is_empty() {
+            return;
+        }
+        MaelstromMessage::get_replicate_msgs(self, writes, timestamp)
+            .into_iter()
+            .for_each(|x| self.post_ack(x));
+    }
+
+    /// What `debug_state` answers with: the size of everything the node is holding.
+    pub fn debug_state(&self) -> Value {
+        json!({
+            "node_id": self.node_id,
+            "config": self.config,
+            "seen_messages": self.delivery_order.len(),
+            "store_keys": self.store.len(),
+            "clock": self.clock,
+            "clocks": self.clocks,
+            "next_msg_id": self.msg_ids.last() + 1,
+            "raft": self.raft.as_ref().map(|raft| json!({
+                "role": format!("{:?}", raft.role),
+                "term": raft.term,
+                "leader_id": raft.leader_id,
+                "log_len": raft.log.len() - 1,
+                "commit_index": raft.commit_index,
+                "last_applied": raft.last_applied,
+                "kv_keys": raft.kv.len(),
+            })),
+            "crdt": self.crdt.as_ref().map(|x| x.read()),
+            "causal_delivered": self.causal.as_ref().map(|x| &x.delivered),
+            "election": self.election.as_ref().map(|x| json!({
+                "kind": x.kind,
+                "leader": x.leader,
+                "token": x.token,
+                "lease": x.lease,
+            })),
+        })
+    }
+
+    /// What `debug_peers` answers with: who the node knows about and talks to.
+    pub fn debug_peers(&self) -> Value {
+        json!({
+            "node_ids": self.node_ids,
+            "neighbours": self.topology.get(&self.node_id),
+            "topology": self.topology,
+            "raft": self.raft.as_ref().map(|raft| json!({
+                "voted_for": raft.voted_for,
+                "next_index": raft.next_index,
+                "match_index": raft.match_index,
+            })),
+            "membership": self.membership.as_ref().map(|swim| json!({
+                "live": swim.view(Status::Alive),
+                "suspect": swim.view(Status::Suspect),
+                "dead": swim.view(Status::Dead),
+                "members": swim.members,
+            })),
+            "phi": self.failure_detector.as_ref().map(|detector| detector
+                .peers
+                .keys()
+                .map(|x| (x.to_string(), detector.phi(x).into()))
+                .collect::<serde_json::Map<String, Value>>()),
+            "plumtree": self.plumtree.as_ref().map(|plumtree| json!({
+                "eager": plumtree.eager,
+                "lazy": plumtree.lazy,
+            })),
+        })
+    }
+
+    /// What `debug_pending` answers with: messages waiting on an ack, and requests waiting
+    /// on the raft log or the leader.
+    pub fn debug_pending(&self) -> Value {
+        let now = runtime::now();
+        let awaiting_ack: Vec<Value> = self
+            .awaiting_ack
+            .iter()
+            .map(|x| {
+                json!({
+                    "dest": x.msg.dest,
+                    "msg_id": x.msg.body.msg_id,
+                    "type": x.msg.body.msg_type,
+                    "waiting_ms": (now - x.timestamp).as_millis() as u64,
+                })
+            })
+            .collect();
+        json!({
+            "awaiting_ack": awaiting_ack,
+            "parked": self
+                .parked
+                .iter()
+                .map(|(peer, x)| (peer.to_string(), x.len().into()))
+                .collect::<serde_json::Map<String, Value>>(),
+            "raft_pending": self.raft.as_ref().map(|x| State::rpc_table(x.pending.iter())),
+            "raft_forwarded": self.raft.as_ref().map(|x| {
+                State::rpc_table(x.forwarded.iter().map(|(id, x)| (id, &x.request)))
+            }),
+            "causal_pending": self.causal.as_ref().map(|x| &x.pending),
+            "total_order_pending": self.total_order.as_ref().map(|x| &x.pending),
+            "plumtree_missing": self.plumtree.as_ref().map(|x| &x.missing),
+            "election_request": self.election.as_ref().map(|x| &x.request),
+        })
+    }
+
+    /// Who is waiting on each entry of a raft request table.
+    fn rpc_table<'a>(table: impl Iterator<Item = (&'a usize, &'a MaelstromMessage)>) -> Value {
+        table
+            .map(|(id, msg)| {
+                let waiting = json!({ "src": msg.src, "msg_id": msg.body.msg_id });
+                (id.to_string(), waiting)
+            })
+            .collect::<serde_json::Map<String, Value>>()
+            .into()
+    }
+
+    /// Makes a broadcast value visible to `read`, returning whether it was new.
+    fn see(&mut self, message: usize) -> bool {
+        if self.seen_messages.contains(&message) {
+            return false;
+        }
+        self.seen_messages.add(message);
+        self.delivery_order.push(message);
+        true
+    }
+
+    /// Makes a broadcast value visible to `read`, or holds it back until it may be in
+    /// causal mode. Values fresh from a client get their origin and deps filled in here.
+    /// Returns whether the value was new, and so should be passed on.
+    fn deliver_broadcast(&mut self, msg: &mut MaelstromMessage) -> bool {
+        let message: usize = msg.body.message.unwrap();
+        let Some(causal) = &mut self.causal else {
+            return self.see(message);
+        };
+        match (&msg.body.origin, &msg.body.deps) {
+            (Some(origin), Some(deps)) => {
+                let new = causal.is_new(origin, deps);
+                let delivered = causal.receive(origin.clone(), deps.clone(), message);
+                for message in delivered {
+                    self.see(message);
+                }
+                new
+            }
+            _ if self.seen_messages.contains(&message) => false,
+            _ => {
+                msg.body.deps = Some(causal.broadcast(&self.node_id));
+                msg.body.origin = Some(self.node_id.clone());
+                self.see(message)
+            }
+        }
+    }
+
+    /// Handles one message, counting it and everything it caused to be sent.
+    pub fn handle(&mut self, msg: MaelstromMessage) {
+        self.metrics.received(&msg);
+        if let Some(detector) = self.failure_detector.as_mut().filter(|_| msg.src.is_node()) {
+            detector.heartbeat(&msg.src);
+        }
+        if msg.src.is_node() {
+            self.clocks.observe(&self.node_id, &msg.body);
+        }
+        let handler = serde_json::to_value(&msg.body.msg_type).unwrap();
+        let start = runtime::now();
+        let out = MaelstromMessage::capture(|| self.dispatch(msg));
+        self.metrics
+            .handled(handler.as_str().unwrap_or_default(), runtime::now() - start);
+        self.send(out);
+    }
+
+    /// Posts what a handler or tick produced, noting it and the queues it left behind.
+    fn send(&mut self, out: Vec<MaelstromMessage>) {
+        for mut msg in out {
+            if msg.dest.is_node() {
+                self.clocks.stamp(&self.node_id, &mut msg.body);
+                if let Some(detector) = &mut self.failure_detector {
+                    detector.sent(&msg.dest);
+                }
+            }
+            self.metrics.sent(&msg);
+            msg.post();
+        }
+        self.metrics.queue("awaiting_ack", self.awaiting_ack.len());
+        let parked = self.parked.values().map(|x| x.len()).sum();
+        self.metrics.queue("parked", parked);
+        if let Some(raft) = &self.raft {
+            self.metrics.queue("raft_pending", raft.pending.len());
+            self.metrics.queue("raft_forwarded", raft.forwarded.len());
+        }
+        if let Some(causal) = &self.causal {
+            self.metrics.queue("causal_pending", causal.pending.len());
+        }
+        if let Some(total_order) = &self.total_order {
+            self.metrics
+                .queue("total_order_pending", total_order.pending.len());
+        }
+    }
+
+    fn dispatch(&mut self, mut msg: MaelstromMessage) {
+        match &msg.body.msg_type {
+            MessageType::InitOk
+            | MessageType::EchoOk
+            | MessageType::GenerateOk
+            | MessageType::TopologyOk
+            | MessageType::AddOk => {}
+            MessageType::BroadcastOk
+            | MessageType::TxnOk
+            | MessageType::SequencerTokenOk
+            | MessageType::IHaveOk => {
+                self.try_remove_ack(&msg);
+                if self.config.broadcast == BroadcastMode::Raft {
+                    if let Some(raft) = &mut self.raft {
+                        raft.relay(&msg);
+                    }
+                }
+            }
+            MessageType::Init => {
+                self.node_ids = msg.body.node_ids.clone().unwrap();
+                self.node_id = msg.body.node_id.clone().unwrap();
+                if let Some(kind) = self.config.crdt {
+                    self.crdt = Some(CrdtNode::new(kind));
+                }
+                if self.config.broadcast == BroadcastMode::Causal {
+                    self.causal = Some(CausalBroadcast::default());
+                }
+                if matches!(
+                    self.config.broadcast,
+                    BroadcastMode::Sequencer | BroadcastMode::RotatingSequencer
+                ) {
+                    self.total_order = Some(TotalOrder::new(
+                        self.config.broadcast,
+                        self.node_id.clone(),
+                        self.node_ids.clone(),
+                        self.msg_ids.clone(),
+                    ));
+                }
+                if self.config.membership {
+                    self.membership = Some(Swim::new(
+                        self.node_id.clone(),
+                        &self.node_ids,
+                        self.msg_ids.clone(),
+                    ));
+                }
+                if let Some(threshold) = self.config.phi_threshold {
+                    let peers: Vec<NodeId> = self
+                        .node_ids
+                        .iter()
+                        .filter(|x| **x != self.node_id)
+                        .cloned()
+                        .collect();
+                    self.failure_detector = Some(PhiAccrual::new(threshold, &peers));
+                }
+                if self.config.broadcast == BroadcastMode::Plumtree {
+                    self.plumtree = Some(Plumtree::new(
+                        self.node_id.clone(),
+                        &self.node_ids,
+                        self.msg_ids.clone(),
+                    ));
+                }
+                if let Some(kind) = self.config.election {
+                    self.election = Some(Election::new(
+                        kind,
+                        self.node_id.clone(),
+                        self.node_ids.clone(),
+                        self.msg_ids.clone(),
+                    ));
+                }
+                if self.config.raft || self.config.broadcast == BroadcastMode::Raft {
+                    self.raft = Some(Raft::new(
+                        self.node_id.clone(),
+                        self.node_ids.clone(),
+                        self.msg_ids.clone(),
+                    ));
+                }
+                setup_logging(Some(self.node_id.to_string()));
+                log::info!("init complete");
+            }
+            MessageType::Echo => {}
+            MessageType::Generate => {
+                msg.body.id = Some(self.ids.next(&self.node_id, &mut self.clocks.hlc));
+            }
+            MessageType::Broadcast => {
+                info!(
+                    "Broadcast recieved: {} from {}",
+                    &msg.body.message.unwrap(),
+                    &msg.src
+                );
+                if let Some(total_order) = &mut self.total_order {
+                    let message = msg.body.message.unwrap();
+                    let (delivered, out) = match msg.body.seq {
+                        Some(seq) => (total_order.receive(seq, message), Vec::new()),
+                        None if msg.src.is_node() => total_order.sequence(message),
+                        None => total_order.submit(message),
+                    };
+                    for message in delivered {
+                        self.see(message);
+                    }
+                    out.into_iter().for_each(|x| self.post_ack(x));
+                } else if let Some(plumtree) = &mut self.plumtree {
+                    let message = msg.body.message.unwrap();
+                    let (new, out) = match msg.src.is_client() {
+                        true => plumtree.broadcast(message),
+                        false => plumtree.receive(message, &msg.src),
+                    };
+                    if new {
+                        self.see(message);
+                    }
+                    self.send_plumtree(out);
+                } else if self.config.broadcast == BroadcastMode::Raft {
+                    if let Some(raft) = &mut self.raft {
+                        raft.client_request(msg.clone());
+                    }
+                } else if self.deliver_broadcast(&mut msg) {
+                    //broadcast new message to friends
+                    MaelstromMessage::get_broadcast_msg(self, msg.clone())
+                        .into_iter()
+                        .for_each(|x| self.post_ack(x));
+                }
+            }
+            MessageType::SequencerToken => {
+                if let Some(total_order) = &mut self.total_order {
+                    total_order.take_token(msg.body.round.unwrap(), msg.body.seq.unwrap());
+                }
+            }
+            MessageType::IHave => {
+                if let Some(plumtree) = &mut self.plumtree {
+                    plumtree.ihave(msg.body.messages.as_deref().unwrap_or_default(), &msg.src);
+                }
+            }
+            MessageType::Graft => {
+                if let Some(plumtree) = &mut self.plumtree {
+                    let messages = msg.body.messages.as_deref().unwrap_or_default();
+                    let out = plumtree.graft(messages, &msg.src);
+                    self.send_plumtree(out);
+                }
+            }
+            MessageType::Prune => {
+                if let Some(plumtree) = &mut self.plumtree {
+                    plumtree.prune(&msg.src);
+                }
+            }
+            MessageType::Ping | MessageType::PingOk | MessageType::PingReq => {
+                if let Some(swim) = &mut self.membership {
+                    let out = match msg.body.msg_type {
+                        MessageType::Ping => swim.handle_ping(&msg),
+                        MessageType::PingOk => swim.handle_ping_ok(&msg),
+                        _ => swim.handle_ping_req(&msg),
+                    };
+                    out.into_iter().for_each(|x| x.post());
+                }
+            }
+            // already counted as a sign of life on the way in
+            MessageType::Heartbeat => {}
+            MessageType::PingReqOk => {
+                if let Some(swim) = &mut self.membership {
+                    swim.handle_ping_req_ok(&msg);
+                }
+            }
+            MessageType::Election | MessageType::Coordinator => {
+                let unreachable = self.unreachable_peers();
+                if let Some(election) = &mut self.election {
+                    let out = match msg.body.msg_type {
+                        MessageType::Election => election.handle_election(&msg, &unreachable),
+                        _ => election.handle_coordinator(&msg, &unreachable),
+                    };
+                    out.into_iter().for_each(|x| x.post());
+                }
+            }
+            MessageType::ElectionOk => {
+                if let Some(election) = &mut self.election {
+                    election.handle_election_ok();
+                }
+            }
+            MessageType::Read if msg.body.key.is_none() => {}
+            MessageType::Metrics
+            | MessageType::MetricsOk
+            | MessageType::DebugState
+            | MessageType::DebugStateOk
+            | MessageType::DebugPeers
+            | MessageType::DebugPeersOk
+            | MessageType::DebugPending
+            | MessageType::DebugPendingOk => {}
+            MessageType::Add => {
+                let body = match &mut self.crdt {
+                    Some(crdt) => {
+                        match crdt.add(&self.node_id.to_string(), msg.body.element, msg.body.delta)
+                        {
+                            Ok(()) => msg.body.reply(MessageType::AddOk),
+                            Err(text) => msg
+                                .body
+                                .error(MaelstromMessageBody::MALFORMED_REQUEST, text),
+                        }
+                    }
+                    None => msg
+                        .body
+                        .error(MaelstromMessageBody::NOT_SUPPORTED, "no crdt is enabled"),
+                };
+                MaelstromMessage {
+                    src: self.node_id.clone(),
+                    dest: msg.src.clone(),
+                    body,
+                }
+                .post();
+            }
+            MessageType::Gossip => {
+                if let (Some(crdt), Some(remote)) = (&mut self.crdt, msg.body.crdt.clone()) {
+                    if let Err(e) = crdt.merge(remote) {
+                        info!("Bad gossip from {}: {}", msg.src, e);
+                    }
+                }
+            }
+            MessageType::Read | MessageType::Write | MessageType::Cas => match &mut self.raft {
+                Some(raft) => raft.client_request(msg.clone()),
+                None => MaelstromMessage {
+                    src: self.node_id.clone(),
+                    dest: msg.src.clone(),
+                    body: msg
+                        .body
+                        .error(MaelstromMessageBody::NOT_SUPPORTED, "lin-kv is not enabled"),
+                }
+                .post(),
+            },
+            MessageType::ReadOk
+            | MessageType::WriteOk
+            | MessageType::CasOk
+            | MessageType::Error => match &mut self.election {
+                Some(election) if msg.src == NodeId::lin_kv() => {
+                    let out = election.handle_kv_reply(&msg);
+                    out.into_iter().for_each(|x| x.post());
+                }
+                _ => {
+                    if let Some(raft) = &mut self.raft {
+                        raft.relay(&msg);
+                    }
+                }
+            },
+            MessageType::RequestVote => {
+                if let Some(raft) = &mut self.raft {
+                    raft.handle_request_vote(&msg);
+                }
+            }
+            MessageType::RequestVoteOk => {
+                if let Some(raft) = &mut self.raft {
+                    raft.handle_request_vote_ok(&msg);
+                }
+            }
+            MessageType::AppendEntries => {
+                if let Some(raft) = &mut self.raft {
+                    raft.handle_append_entries(&msg);
+                }
+            }
+            MessageType::AppendEntriesOk => {
+                if let Some(raft) = &mut self.raft {
+                    raft.handle_append_entries_ok(&msg);
+                }
+            }
+            MessageType::Topology => {
+                self.topology = msg.body.topology.clone().unwrap();
+                info!("Topology: {:?}", self.topology);
+            }
+            MessageType::Txn => {
+                let txn = msg.body.txn.take().unwrap();
+                info!("Txn recieved: {:?} from {}", txn, &msg.src);
+                if msg.src.is_node() {
+                    // replicated writes from a peer, only needs the ack back
+                    let timestamp = msg.body.timestamp.unwrap();
+                    self.clock = self.clock.max(timestamp);
+                    self.apply_txn(txn, timestamp, &msg.src);
+                } else {
+                    self.clock += 1;
+                    let (timestamp, node_id) = (self.clock, self.node_id.clone());
+                    let (txn, writes) = match self.config.isolation {
+                        Isolation::ReadUncommitted => {
+                            let txn = self.apply_txn(txn, timestamp, &node_id);
+                            let writes = txn
+                                .iter()
+                                .filter(|op| matches!(op, MicroOp::Write(_, _)))
+                                .cloned()
+                                .collect();
+                            (txn, writes)
+                        }
+                        Isolation::ReadCommitted => {
+                            let (txn, writes) = self.buffer_txn(txn);
+                            self.apply_txn(writes.clone(), timestamp, &node_id);
+                            (txn, writes)
+                        }
+                    };
+                    self.replicate_txn(writes, timestamp);
+                    msg.body.txn = Some(txn);
+                }
+            }
+        }
+        self.take_raft_broadcasts();
+        self.take_leader_changes();
+        if let Ok(r) = msg.get_response(self) {
+            r.post()
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PostAck {
+    pub timestamp: Duration,
+    pub msg: MaelstromMessage,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        nemesis::Fault,
+        sim::cluster::{self, TIMEOUT},
+    };
+
+    #[test]
+    fn debug_messages_show_raft_and_peers() {
+        let mut sim = cluster::start(
+            3,
+            Config {
+                raft: true,
+                ..Config::default()
+            },
+        );
+        sim.run_for(Duration::from_millis(500));
+        let node_ids = sim.node_ids();
+        let mut debug = |node_id: &NodeId, msg_type| {
+            sim.call(node_id, MaelstromMessageBody::new(msg_type), TIMEOUT)
+                .unwrap()
+                .body
+                .debug
+                .unwrap()
+        };
+        let leaders = node_ids
+            .iter()
+            .filter(|x| debug(x, MessageType::DebugState)["raft"]["role"] == "Leader")
+            .count();
+        assert_eq!(leaders, 1);
+        let peers = debug(&NodeId::Node(0), MessageType::DebugPeers);
+        assert_eq!(peers["neighbours"], json!(["n1", "n2"]));
+        let pending = debug(&NodeId::Node(0), MessageType::DebugPending);
+        assert_eq!(pending["awaiting_ack"], json!([]));
+        assert!(pending["raft_pending"].is_object());
+    }
+
+    #[test]
+    fn acks_must_answer_the_request_by_id_and_type() {
+        let mut sim = cluster::start(2, Config::default());
+        let (n0, n1) = (NodeId::Node(0), NodeId::Node(1));
+        let second = Duration::from_secs(1);
+        sim.nemesis
+            .add(runtime::now(), second, Fault::Isolate(Some(n1.clone())));
+        let reply = sim
+            .call(
+                &n0,
+                MaelstromMessageBody {
+                    message: Some(1),
+                    ..MaelstromMessageBody::new(MessageType::Broadcast)
+                },
+                TIMEOUT,
+            )
+            .unwrap();
+        assert_eq!(reply.body.msg_id, None);
+        let node = sim.nodes.get_mut(&n0).unwrap();
+        let msg_id = node.awaiting_ack[0].msg.body.msg_id;
+        let ack = |msg_type| MaelstromMessage {
+            src: n1.clone(),
+            dest: n0.clone(),
+            body: MaelstromMessageBody {
+                in_reply_to: msg_id,
+                ..MaelstromMessageBody::new(msg_type)
+            },
+        };
+        MaelstromMessage::capture(|| node.handle(ack(MessageType::TxnOk)));
+        assert_eq!(node.awaiting_ack.len(), 1);
+        MaelstromMessage::capture(|| node.handle(ack(MessageType::BroadcastOk)));
+        assert!(node.awaiting_ack.is_empty());
+    }
+}
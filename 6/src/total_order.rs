@@ -0,0 +1,253 @@
+use std::collections::{BTreeMap, HashSet};
+
+use serde::Serialize;
+
+use crate::{
+    config::BroadcastMode,
+    message::{MaelstromMessage, MaelstromMessageBody, MessageType, MsgIds},
+    node_id::NodeId,
+};
+
+/// Total order broadcast through a sequencer. Every value is given the next sequence
+/// number by whoever is sequencing and sent to every node, and nodes only show values in
+/// sequence order, so `read` lists them the same way everywhere.
+///
+/// With a fixed sequencer, the lowest node does all the numbering and everyone else
+/// forwards their values to it; if it's down, nothing gets delivered. With a rotating
+/// sequencer, a token carrying the next sequence number goes round the nodes every tick,
+/// and whoever holds it numbers the values it has queued up.
+#[derive(Serialize, Debug, Clone)]
+pub struct TotalOrder {
+    pub mode: BroadcastMode,
+    pub node_id: NodeId,
+    pub node_ids: Vec<NodeId>,
+    /// sequence number of the next value to deliver
+    pub next: usize,
+    /// sequenced values that arrived ahead of `next`
+    pub pending: BTreeMap<usize, usize>,
+    /// every value known to have been sequenced, so retransmissions aren't numbered twice
+    pub sequenced: HashSet<usize>,
+    /// next sequence number to hand out, while sequencing
+    pub next_seq: usize,
+    /// values from clients waiting on the token
+    pub queued: Vec<usize>,
+    pub holding_token: bool,
+    /// the last round of the token this node held, to spot retransmitted tokens
+    pub round: usize,
+    #[serde(skip)]
+    msg_ids: MsgIds,
+}
+
+impl TotalOrder {
+    pub fn new(
+        mode: BroadcastMode,
+        node_id: NodeId,
+        node_ids: Vec<NodeId>,
+        msg_ids: MsgIds,
+    ) -> Self {
+        let first = node_ids.iter().min() == Some(&node_id);
+        TotalOrder {
+            mode,
+            node_id,
+            node_ids,
+            next: 0,
+            pending: BTreeMap::new(),
+            sequenced: HashSet::new(),
+            next_seq: 0,
+            queued: Vec::new(),
+            holding_token: first,
+            round: 0,
+            msg_ids,
+        }
+    }
+
+    pub fn sequencer(&self) -> &NodeId {
+        self.node_ids.iter().min().unwrap()
+    }
+
+    /// A value from a client. Returns what can be delivered here and what to send out.
+    pub fn submit(&mut self, message: usize) -> (Vec<usize>, Vec<MaelstromMessage>) {
+        if self.sequenced.contains(&message) || self.queued.contains(&message) {
+            return (Vec::new(), Vec::new());
+        }
+        match self.mode {
+            BroadcastMode::Sequencer if self.sequencer() == &self.node_id => self.sequence(message),
+            BroadcastMode::Sequencer => {
+                let sequencer = self.sequencer().clone();
+                (Vec::new(), vec![self.broadcast(&sequencer, message, None)])
+            }
+            _ => {
+                self.queued.push(message);
+                (Vec::new(), Vec::new())
+            }
+        }
+    }
+
+    /// Numbers `message` and sends it to every other node.
+    pub fn sequence(&mut self, message: usize) -> (Vec<usize>, Vec<MaelstromMessage>) {
+        if !self.sequenced.insert(message) {
+            return (Vec::new(), Vec::new());
+        }
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let out = self
+            .node_ids
+            .iter()
+            .filter(|x| **x != self.node_id)
+            .map(|dest| self.broadcast(dest, message, Some(seq)))
+            .collect();
+        (self.receive(seq, message), out)
+    }
+
+    /// A sequenced value, returning the values that are now next in line.
+    pub fn receive(&mut self, seq: usize, message: usize) -> Vec<usize> {
+        self.sequenced.insert(message);
+        if seq >= self.next {
+            self.pending.insert(seq, message);
+        }
+        let mut delivered = Vec::new();
+        while let Some(message) = self.pending.remove(&self.next) {
+            delivered.push(message);
+            self.next += 1;
+        }
+        delivered
+    }
+
+    /// Takes the token, unless it's a retransmission of one already passed on.
+    pub fn take_token(&mut self, round: usize, seq: usize) {
+        if round > self.round {
+            self.round = round;
+            self.next_seq = self.next_seq.max(seq);
+            self.holding_token = true;
+        }
+    }
+
+    /// With a rotating sequencer, numbers everything queued while holding the token and
+    /// passes it on to the next node.
+    pub fn tick(&mut self) -> (Vec<usize>, Vec<MaelstromMessage>) {
+        let (mut delivered, mut out) = (Vec::new(), Vec::new());
+        if self.mode != BroadcastMode::RotatingSequencer || !self.holding_token {
+            return (delivered, out);
+        }
+        for message in std::mem::take(&mut self.queued) {
+            let (d, o) = self.sequence(message);
+            delivered.extend(d);
+            out.extend(o);
+        }
+        let i = self
+            .node_ids
+            .iter()
+            .position(|x| *x == self.node_id)
+            .unwrap();
+        let next = self.node_ids[(i + 1) % self.node_ids.len()].clone();
+        if next != self.node_id {
+            self.holding_token = false;
+            out.push(MaelstromMessage {
+                src: self.node_id.clone(),
+                dest: next,
+                body: MaelstromMessageBody {
+                    msg_id: Some(self.msg_ids.next()),
+                    round: Some(self.round + 1),
+                    seq: Some(self.next_seq),
+                    ..MaelstromMessageBody::new(MessageType::SequencerToken)
+                },
+            });
+        }
+        (delivered, out)
+    }
+
+    fn broadcast(&self, dest: &NodeId, message: usize, seq: Option<usize>) -> MaelstromMessage {
+        MaelstromMessage {
+            src: self.node_id.clone(),
+            dest: dest.clone(),
+            body: MaelstromMessageBody {
+                msg_id: Some(self.msg_ids.next()),
+                message: Some(message),
+                seq,
+                ..MaelstromMessageBody::new(MessageType::Broadcast)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{config::Config, sim::cluster};
+
+    #[test]
+    fn delivers_in_sequence_order_once() {
+        let node_ids = vec![NodeId::Node(0), NodeId::Node(1)];
+        let mut node = TotalOrder::new(
+            BroadcastMode::Sequencer,
+            NodeId::Node(1),
+            node_ids,
+            MsgIds::default(),
+        );
+        assert!(node.receive(1, 20).is_empty());
+        assert!(node.receive(2, 30).is_empty());
+        assert_eq!(node.receive(0, 10), [10, 20, 30]);
+        assert!(node.receive(1, 20).is_empty());
+        // already sequenced, so not forwarded to n0 again
+        assert!(node.submit(20).1.is_empty());
+        assert_eq!(node.submit(40).1[0].dest, NodeId::Node(0));
+    }
+
+    #[test]
+    fn passes_the_token_under_the_nodes_own_msg_ids() {
+        let msg_ids = MsgIds::default();
+        let node_ids = vec![NodeId::Node(0), NodeId::Node(1)];
+        let mut node = TotalOrder::new(
+            BroadcastMode::RotatingSequencer,
+            NodeId::Node(0),
+            node_ids,
+            msg_ids.clone(),
+        );
+        node.queued.push(10);
+        let (delivered, out) = node.tick();
+        assert_eq!(delivered, [10]);
+        let ids: Vec<_> = out.iter().map(|x| x.body.msg_id.unwrap()).collect();
+        assert_eq!(ids, [1, 2]);
+        assert_eq!(out[1].body.msg_type, MessageType::SequencerToken);
+        assert_eq!(out[1].body.round, Some(1));
+        assert_eq!(msg_ids.last(), 2);
+    }
+
+    #[test]
+    fn reads_the_same_everywhere() {
+        for mode in [
+            BroadcastMode::Sequencer,
+            BroadcastMode::RotatingSequencer,
+            BroadcastMode::Raft,
+        ] {
+            let mut sim = cluster::start(
+                3,
+                Config {
+                    broadcast: mode,
+                    ..Config::default()
+                },
+            );
+            sim.jitter = Duration::from_millis(20);
+            // raft needs a leader first
+            sim.run_for(Duration::from_millis(500));
+            for message in 0..20 {
+                cluster::broadcast(&mut sim, &NodeId::Node(message % 3), message);
+                sim.run_for(Duration::from_millis(2));
+            }
+            sim.run_for(Duration::from_millis(500));
+            let reads: Vec<Vec<usize>> = cluster::read_all(&mut sim)
+                .into_iter()
+                .map(|x| x.messages.unwrap())
+                .collect();
+            assert_eq!(reads[0].len(), 20, "{:?}", mode);
+            assert!(
+                reads.iter().all(|x| *x == reads[0]),
+                "{:?}: {:?}",
+                mode,
+                reads
+            );
+        }
+    }
+}
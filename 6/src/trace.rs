@@ -0,0 +1,237 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, history::micros, message::MaelstromMessage, runtime, state::State};
+
+/// Something that happened to a node, in the order it happened.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Event {
+    /// What the node ran with, so a replay can set up the same node, rng and clock.
+    Start {
+        seed: u64,
+        #[serde(with = "micros", default)]
+        epoch: Duration,
+        config: Config,
+    },
+    /// A line read from stdin, exactly as it came.
+    In { line: String },
+    /// The main loop's timer going off.
+    Tick,
+    /// Something the node posted while handling the `In` or `Tick` before it.
+    Out { msg: Box<MaelstromMessage> },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TraceEntry {
+    #[serde(with = "micros")]
+    pub time: Duration,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// Runs one input through the node and returns what it posted. Recording and replay both
+/// go through here so they can't drift apart.
+fn apply(state: &mut State, event: &Event) -> Vec<MaelstromMessage> {
+    match event {
+        Event::In { line } => {
+            let msg: MaelstromMessage = serde_json::from_str(line).unwrap();
+            MaelstromMessage::capture(|| state.handle(msg))
+        }
+        Event::Tick => MaelstromMessage::capture(|| state.tick()),
+        Event::Start { .. } | Event::Out { .. } => Vec::new(),
+    }
+}
+
+/// Writes a trace of a live node. The node is moved onto virtual time with a seeded rng,
+/// with the clock stepped up to the wall clock before every input, so everything it does
+/// is a function of the trace.
+pub struct Recorder {
+    out: BufWriter<File>,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &str, config: &Config) -> std::io::Result<Self> {
+        let seed: u64 = rand::random();
+        let epoch = runtime::unix_time();
+        runtime::install_virtual(seed, epoch);
+        let mut recorder = Recorder {
+            out: BufWriter::new(File::create(path)?),
+            start: Instant::now(),
+        };
+        recorder.write(Event::Start {
+            seed,
+            epoch,
+            config: config.clone(),
+        });
+        Ok(recorder)
+    }
+
+    pub fn input(&mut self, state: &mut State, line: String) {
+        self.run(state, Event::In { line });
+    }
+
+    pub fn tick(&mut self, state: &mut State) {
+        self.run(state, Event::Tick);
+    }
+
+    fn run(&mut self, state: &mut State, event: Event) {
+        runtime::advance_to(self.start.elapsed());
+        let out = apply(state, &event);
+        self.write(event);
+        for msg in out {
+            msg.post();
+            self.write(Event::Out { msg: Box::new(msg) });
+        }
+        // flushed every time so a node that's killed still leaves a usable trace
+        let _ = self.out.flush();
+    }
+
+    fn write(&mut self, event: Event) {
+        let entry = TraceEntry {
+            time: runtime::now(),
+            event,
+        };
+        let _ = writeln!(self.out, "{}", serde_json::to_string(&entry).unwrap());
+    }
+}
+
+/// Where a replay posted something other than what was recorded.
+#[derive(Debug, Clone)]
+pub struct Mismatch {
+    pub time: Duration,
+    pub input: Event,
+    pub expected: Vec<MaelstromMessage>,
+    pub actual: Vec<MaelstromMessage>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReplayReport {
+    pub inputs: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+
+pub fn from_json_lines(lines: &str) -> Result<Vec<TraceEntry>, serde_json::Error> {
+    lines
+        .lines()
+        .filter(|x| !x.trim().is_empty())
+        .map(serde_json::from_str)
+        .collect()
+}
+
+/// Feeds a recorded trace back into a fresh node at the recorded times and compares what
+/// it posts against what was recorded after each input.
+pub fn replay(trace: &[TraceEntry]) -> ReplayReport {
+    let mut report = ReplayReport::default();
+    let Some(Event::Start {
+        seed,
+        epoch,
+        config,
+    }) = trace.first().map(|x| &x.event)
+    else {
+        return report;
+    };
+    runtime::install_virtual(*seed, *epoch);
+    let mut state = State::new(config.clone());
+    let mut i = 1;
+    while i < trace.len() {
+        let entry = &trace[i];
+        i += 1;
+        if matches!(entry.event, Event::Out { .. }) {
+            continue;
+        }
+        let mut expected = Vec::new();
+        while let Some(Event::Out { msg }) = trace.get(i).map(|x| &x.event) {
+            expected.push(*msg.clone());
+            i += 1;
+        }
+        runtime::advance_to(entry.time);
+        let actual = apply(&mut state, &entry.event);
+        report.inputs += 1;
+        if serde_json::to_value(&actual).unwrap() != serde_json::to_value(&expected).unwrap() {
+            report.mismatches.push(Mismatch {
+                time: entry.time,
+                input: entry.event.clone(),
+                expected,
+                actual,
+            });
+        }
+    }
+    runtime::uninstall_virtual();
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        message::{MaelstromMessageBody, MessageType},
+        node_id::NodeId,
+    };
+
+    fn line(body: MaelstromMessageBody) -> String {
+        let msg = MaelstromMessage {
+            src: NodeId::Client(1),
+            dest: NodeId::Node(0),
+            body,
+        };
+        serde_json::to_string(&msg).unwrap()
+    }
+
+    #[test]
+    fn recorded_trace_replays_the_same() {
+        let path = std::env::temp_dir().join(format!("glomers-trace-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let config = Config::default();
+        // in the order main makes them, so the node is built on the recorded rng
+        let mut recorder = Recorder::create(path, &config).unwrap();
+        let mut state = State::new(config.clone());
+        let inputs = [
+            MaelstromMessageBody {
+                msg_id: Some(1),
+                node_id: Some(NodeId::Node(0)),
+                node_ids: Some(vec![NodeId::Node(0)]),
+                ..MaelstromMessageBody::new(MessageType::Init)
+            },
+            MaelstromMessageBody {
+                msg_id: Some(2),
+                ..MaelstromMessageBody::new(MessageType::Generate)
+            },
+            MaelstromMessageBody {
+                msg_id: Some(3),
+                ..MaelstromMessageBody::new(MessageType::Generate)
+            },
+        ];
+        for body in inputs {
+            recorder.input(&mut state, line(body));
+            recorder.tick(&mut state);
+        }
+        drop(recorder);
+        runtime::uninstall_virtual();
+
+        let mut trace = from_json_lines(&std::fs::read_to_string(path).unwrap()).unwrap();
+        std::fs::remove_file(path).unwrap();
+        let report = replay(&trace);
+        assert_eq!(report.inputs, 6);
+        assert!(report.mismatches.is_empty(), "{:?}", report);
+
+        // an edited output is what a regression looks like
+        let out = trace
+            .iter_mut()
+            .find_map(|x| match &mut x.event {
+                Event::Out { msg } if msg.body.msg_type == MessageType::GenerateOk => Some(msg),
+                _ => None,
+            })
+            .unwrap();
+        out.body.id = Some(String::from("not-what-it-said"));
+        let report = replay(&trace);
+        assert_eq!(report.mismatches.len(), 1);
+        assert_eq!(report.mismatches[0].expected[0].body.in_reply_to, Some(2));
+    }
+}
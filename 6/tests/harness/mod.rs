@@ -0,0 +1,182 @@
+//! Runs the compiled node as a subprocess and talks Maelstrom's JSON protocol to it over
+//! stdin/stdout, standing in for Maelstrom's client and the rest of the cluster. The
+//! binary is the one cargo built for the tests unless `GLOMERS_BIN` names another, as
+//! `./run.sh workloads` does with the release build the challenges run.
+
+// each test binary uses its own share of the helpers
+#![allow(dead_code)]
+
+use std::{
+    collections::VecDeque,
+    env,
+    io::{BufRead, BufReader, Write},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use rusty_glomers::{
+    message::{MaelstromMessage, MaelstromMessageBody, MessageType},
+    node_id::NodeId,
+};
+
+pub const TIMEOUT: Duration = Duration::from_secs(2);
+pub const CLIENT: NodeId = NodeId::Client(1);
+pub const BIN_VAR: &str = "GLOMERS_BIN";
+
+pub struct Node {
+    pub node_id: NodeId,
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Receiver<MaelstromMessage>,
+    /// output read while looking for something else, not yet expected
+    unclaimed: VecDeque<MaelstromMessage>,
+    next_msg_id: usize,
+}
+
+impl Node {
+    /// Starts the node binary with `env` on top of the test's own environment.
+    pub fn spawn(env: &[(&str, &str)]) -> Self {
+        let bin = env::var(BIN_VAR).unwrap_or(env!("CARGO_BIN_EXE_rusty-glomers").to_string());
+        let mut child = Command::new(&bin)
+            .envs(env.iter().copied())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap_or_else(|e| panic!("starting {}: {}", bin, e));
+        let stdin = child.stdin.take().unwrap();
+        let out = child.stdout.take().unwrap();
+        let (tx, stdout) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(out).lines().map_while(Result::ok) {
+                let msg = serde_json::from_str(&line)
+                    .unwrap_or_else(|e| panic!("node wrote {:?}: {}", line, e));
+                if tx.send(msg).is_err() {
+                    return;
+                }
+            }
+        });
+        Node {
+            node_id: NodeId::UNKNOWN,
+            child,
+            stdin,
+            stdout,
+            unclaimed: VecDeque::new(),
+            next_msg_id: 1,
+        }
+    }
+
+    /// Spawns a node and inits it as `node_id` of `node_ids`.
+    pub fn start(node_id: &str, node_ids: &[&str], env: &[(&str, &str)]) -> Self {
+        let mut node = Node::spawn(env);
+        node.node_id = node_id.parse().unwrap();
+        let init = MaelstromMessageBody {
+            node_id: Some(node.node_id.clone()),
+            node_ids: Some(node_ids.iter().map(|x| x.parse().unwrap()).collect()),
+            ..MaelstromMessageBody::new(MessageType::Init)
+        };
+        let msg_id = node.send(&CLIENT, init);
+        node.expect_reply(msg_id, MessageType::InitOk);
+        node
+    }
+
+    /// Sends `body` from `src` with a fresh msg_id (unless it has one), returning the msg_id.
+    pub fn send(&mut self, src: &NodeId, body: MaelstromMessageBody) -> usize {
+        let msg_id = body.msg_id.unwrap_or_else(|| {
+            self.next_msg_id += 1;
+            self.next_msg_id
+        });
+        let msg = MaelstromMessage {
+            src: src.clone(),
+            dest: self.node_id.clone(),
+            body: MaelstromMessageBody {
+                msg_id: Some(msg_id),
+                ..body
+            },
+        };
+        writeln!(self.stdin, "{}", msg).unwrap();
+        msg_id
+    }
+
+    /// Sends a client request and expects a reply of type `reply`.
+    pub fn request(&mut self, body: MaelstromMessageBody, reply: MessageType) -> MaelstromMessage {
+        let msg_id = self.send(&CLIENT, body);
+        self.expect_reply(msg_id, reply)
+    }
+
+    /// The next message the node sends that matches `f`, or whatever turned up instead
+    /// once `timeout` passes.
+    pub fn expect(
+        &mut self,
+        timeout: Duration,
+        f: impl Fn(&MaelstromMessage) -> bool,
+    ) -> Result<MaelstromMessage, Vec<MaelstromMessage>> {
+        if let Some(i) = self.unclaimed.iter().position(&f) {
+            return Ok(self.unclaimed.remove(i).unwrap());
+        }
+        let deadline = Instant::now() + timeout;
+        let mut skipped = Vec::new();
+        while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+            match self.stdout.recv_timeout(left) {
+                Ok(msg) if f(&msg) => {
+                    self.unclaimed.extend(skipped);
+                    return Ok(msg);
+                }
+                Ok(msg) => skipped.push(msg),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => panic!("node exited"),
+            }
+        }
+        self.unclaimed.extend(skipped.iter().cloned());
+        Err(skipped)
+    }
+
+    /// The reply to `msg_id`, which must be of type `reply`.
+    pub fn expect_reply(&mut self, msg_id: usize, reply: MessageType) -> MaelstromMessage {
+        let msg = self
+            .expect(TIMEOUT, |x| x.body.in_reply_to == Some(msg_id))
+            .unwrap_or_else(|seen| {
+                panic!("no reply to {} in {:?}, got {:?}", msg_id, TIMEOUT, seen)
+            });
+        assert_eq!(msg.body.msg_type, reply, "reply to {}: {}", msg_id, msg);
+        msg
+    }
+
+    /// Fails if the node sends anything at all within `duration`, claimed or not.
+    pub fn expect_no_output_within(&mut self, duration: Duration) {
+        let out = self.collect(duration);
+        assert!(out.is_empty(), "expected silence, got {:?}", out);
+    }
+
+    /// Everything the node sends within `duration`, including output not yet expected.
+    pub fn collect(&mut self, duration: Duration) -> Vec<MaelstromMessage> {
+        let mut out: Vec<MaelstromMessage> = self.unclaimed.drain(..).collect();
+        let deadline = Instant::now() + duration;
+        while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+            match self.stdout.recv_timeout(left) {
+                Ok(msg) => out.push(msg),
+                Err(_) => break,
+            }
+        }
+        out
+    }
+
+    /// Sends `body` from `src` and returns everything the node sends within `duration`.
+    pub fn send_and_collect(
+        &mut self,
+        src: &NodeId,
+        body: MaelstromMessageBody,
+        duration: Duration,
+    ) -> Vec<MaelstromMessage> {
+        self.send(src, body);
+        self.collect(duration)
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
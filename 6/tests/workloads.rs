@@ -0,0 +1,222 @@
+//! End to end tests of the node binary, one or two per workload, over stdin/stdout.
+
+mod harness;
+
+use std::{collections::HashSet, time::Duration};
+
+use harness::{Node, CLIENT};
+use rusty_glomers::{
+    message::{MaelstromMessageBody, MessageType, MicroOp},
+    node_id::NodeId,
+};
+
+#[test]
+fn echo() {
+    let mut node = Node::start("n1", &["n1"], &[]);
+    let reply = node.request(
+        MaelstromMessageBody {
+            echo: Some(String::from("hello")),
+            ..MaelstromMessageBody::new(MessageType::Echo)
+        },
+        MessageType::EchoOk,
+    );
+    assert_eq!(reply.body.echo.as_deref(), Some("hello"));
+    assert_eq!(reply.src, NodeId::Node(1));
+    assert_eq!(reply.dest, CLIENT);
+}
+
+#[test]
+fn unique_ids() {
+    for strategy in ["uuid", "counter", "snowflake", "hlc"] {
+        let mut node = Node::start("n1", &["n1"], &[("GLOMERS_IDS", strategy)]);
+        let ids: HashSet<String> = (0..100)
+            .map(|_| {
+                node.request(
+                    MaelstromMessageBody::new(MessageType::Generate),
+                    MessageType::GenerateOk,
+                )
+                .body
+                .id
+                .unwrap()
+            })
+            .collect();
+        assert_eq!(ids.len(), 100);
+    }
+}
+
+#[test]
+fn broadcast_is_forwarded_until_acked() {
+    let mut node = Node::start("n1", &["n1", "n2"], &[]);
+    node.request(
+        MaelstromMessageBody {
+            topology: Some(
+                [(1, 2), (2, 1)]
+                    .iter()
+                    .map(|(x, y)| (NodeId::Node(*x), vec![NodeId::Node(*y)]))
+                    .collect(),
+            ),
+            ..MaelstromMessageBody::new(MessageType::Topology)
+        },
+        MessageType::TopologyOk,
+    );
+    node.request(
+        MaelstromMessageBody {
+            message: Some(5),
+            ..MaelstromMessageBody::new(MessageType::Broadcast)
+        },
+        MessageType::BroadcastOk,
+    );
+    // unacknowledged, it keeps being resent
+    let out = node.collect(Duration::from_millis(200));
+    let resent: Vec<_> = out
+        .iter()
+        .filter(|x| x.dest == NodeId::Node(2) && x.body.message == Some(5))
+        .collect();
+    assert!(resent.len() > 1, "{:?}", out);
+    node.send(
+        &NodeId::Node(2),
+        MaelstromMessageBody {
+            in_reply_to: resent[0].body.msg_id,
+            ..MaelstromMessageBody::new(MessageType::BroadcastOk)
+        },
+    );
+    node.collect(Duration::from_millis(50));
+    node.expect_no_output_within(Duration::from_millis(200));
+
+    let read = node.request(
+        MaelstromMessageBody::new(MessageType::Read),
+        MessageType::ReadOk,
+    );
+    assert_eq!(read.body.messages, Some(vec![5]));
+}
+
+#[test]
+fn broadcast_from_a_peer_is_not_sent_back() {
+    let mut node = Node::start("n1", &["n1", "n2"], &[]);
+    node.request(
+        MaelstromMessageBody {
+            topology: Some(
+                [(1, 2), (2, 1)]
+                    .iter()
+                    .map(|(x, y)| (NodeId::Node(*x), vec![NodeId::Node(*y)]))
+                    .collect(),
+            ),
+            ..MaelstromMessageBody::new(MessageType::Topology)
+        },
+        MessageType::TopologyOk,
+    );
+    let out = node.send_and_collect(
+        &NodeId::Node(2),
+        MaelstromMessageBody {
+            message: Some(9),
+            ..MaelstromMessageBody::new(MessageType::Broadcast)
+        },
+        Duration::from_millis(100),
+    );
+    assert!(out.iter().all(|x| x.body.message.is_none()), "{:?}", out);
+}
+
+#[test]
+fn read_committed_txn() {
+    let env = [("GLOMERS_ISOLATION", "read-committed")];
+    let mut node = Node::start("n1", &["n1"], &env);
+    let reply = node.request(
+        MaelstromMessageBody {
+            txn: Some(vec![
+                MicroOp::Write(1, 1),
+                MicroOp::Write(1, 2),
+                MicroOp::Read(1, None),
+            ]),
+            ..MaelstromMessageBody::new(MessageType::Txn)
+        },
+        MessageType::TxnOk,
+    );
+    assert_eq!(reply.body.txn.unwrap()[2], MicroOp::Read(1, Some(2)));
+    let reply = node.request(
+        MaelstromMessageBody {
+            txn: Some(vec![MicroOp::Read(1, None)]),
+            ..MaelstromMessageBody::new(MessageType::Txn)
+        },
+        MessageType::TxnOk,
+    );
+    assert_eq!(reply.body.txn, Some(vec![MicroOp::Read(1, Some(2))]));
+}
+
+#[test]
+fn lin_kv_on_a_single_node() {
+    let mut node = Node::start("n1", &["n1"], &[("GLOMERS_RAFT", "1")]);
+    // the lone node has to elect itself before it takes writes
+    let write = MaelstromMessageBody {
+        key: Some(1),
+        value: Some(4.into()),
+        ..MaelstromMessageBody::new(MessageType::Write)
+    };
+    let written = (0..20).any(|_| {
+        let msg_id = node.send(&CLIENT, write.clone());
+        let reply = node
+            .expect(harness::TIMEOUT, |x| x.body.in_reply_to == Some(msg_id))
+            .unwrap();
+        if reply.body.msg_type != MessageType::WriteOk {
+            node.collect(Duration::from_millis(100));
+        }
+        reply.body.msg_type == MessageType::WriteOk
+    });
+    assert!(written);
+    let cas = node.request(
+        MaelstromMessageBody {
+            key: Some(1),
+            from: Some(4),
+            to: Some(6),
+            ..MaelstromMessageBody::new(MessageType::Cas)
+        },
+        MessageType::CasOk,
+    );
+    assert_eq!(cas.dest, CLIENT);
+    let read = node.request(
+        MaelstromMessageBody {
+            key: Some(1),
+            ..MaelstromMessageBody::new(MessageType::Read)
+        },
+        MessageType::ReadOk,
+    );
+    assert_eq!(read.body.value, Some(6.into()));
+}
+
+#[test]
+fn kv_ops_are_rejected_without_raft() {
+    let mut node = Node::start("n1", &["n1"], &[]);
+    let reply = node.request(
+        MaelstromMessageBody {
+            key: Some(1),
+            value: Some(4.into()),
+            ..MaelstromMessageBody::new(MessageType::Write)
+        },
+        MessageType::Error,
+    );
+    assert_eq!(reply.body.code, Some(MaelstromMessageBody::NOT_SUPPORTED));
+}
+
+#[test]
+fn g_counter() {
+    let mut node = Node::start("n1", &["n1", "n2"], &[("GLOMERS_CRDT", "g-counter")]);
+    for delta in [2, 3] {
+        node.request(
+            MaelstromMessageBody {
+                delta: Some(delta),
+                ..MaelstromMessageBody::new(MessageType::Add)
+            },
+            MessageType::AddOk,
+        );
+    }
+    let gossip = node
+        .expect(Duration::from_millis(500), |x| {
+            x.body.msg_type == MessageType::Gossip
+        })
+        .unwrap();
+    assert_eq!(gossip.dest, NodeId::Node(2));
+    let read = node.request(
+        MaelstromMessageBody::new(MessageType::Read),
+        MessageType::ReadOk,
+    );
+    assert_eq!(read.body.value, Some(5.into()));
+}
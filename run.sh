@@ -1,6 +1,7 @@
 #!/bin/bash
 #
 EARLY="1-3c"
+TXN="6"
 MAEBIN="./maelstrom/maelstrom"
 RUSTYBIN="./target/release/rusty-glomers"
 LOGFILE="/home/rcarrier/Projects/rusty-glomers/rusty-glomers.log"
@@ -8,6 +9,7 @@ rm "$LOGFILE"
 
 PRE="cargo build --release --bin rusty-glomers --target-dir=./target --manifest-path="
 BUILD123C="$PRE$EARLY/Cargo.toml"
+BUILD6="$PRE$TXN/Cargo.toml"
 BUILD="$PRE$1/Cargo.toml"
 export CARGO_TARGET_DIR="./"
 print_wait() {
@@ -31,6 +33,12 @@ case "$1" in
     print_wait "$1" "Running unique ID"
     "$MAEBIN" test -w unique-ids --bin "$RUSTYBIN" --time-limit 30 --rate 1000 --node-count 3 --availability total --nemesis partition
     ;;
+"2-ids")
+    # ./run.sh 2-ids [uuid|counter|snowflake|hlc], the id strategies in 6/
+    $BUILD6
+    print_wait "$1" "Running unique ID ($2)"
+    GLOMERS_IDS="$2" "$MAEBIN" test -w unique-ids --bin "$RUSTYBIN" --time-limit 30 --rate 1000 --node-count 3 --availability total --nemesis partition
+    ;;
 "3a")
     $BUILD123C
     print_wait "$1" "Broadcast"
@@ -56,6 +64,47 @@ case "$1" in
     print_wait "$1" "Multi-Node broadcast"
     "$MAEBIN" test -w broadcast --bin "$RUSTYBIN" --node-count 25 --time-limit 20 --rate 100 --latency 100
     ;;
+"4")
+    $BUILD6
+    print_wait "$1" "Grow-only counter"
+    GLOMERS_CRDT="g-counter" "$MAEBIN" test -w g-counter --bin "$RUSTYBIN" --node-count 3 --rate 100 --time-limit 20 --nemesis partition
+    ;;
+"g-set")
+    $BUILD6
+    print_wait "$1" "Grow-only set"
+    GLOMERS_CRDT="g-set" "$MAEBIN" test -w g-set --bin "$RUSTYBIN" --node-count 3 --rate 100 --time-limit 20 --nemesis partition
+    ;;
+"pn-counter")
+    $BUILD6
+    print_wait "$1" "PN counter"
+    GLOMERS_CRDT="pn-counter" "$MAEBIN" test -w pn-counter --bin "$RUSTYBIN" --node-count 3 --rate 100 --time-limit 20 --nemesis partition
+    ;;
+"6a")
+    $BUILD6
+    print_wait "$1" "Single-Node totally-available transactions"
+    "$MAEBIN" test -w txn-rw-register --bin "$RUSTYBIN" --node-count 1 --time-limit 20 --rate 1000 --concurrency 2n --consistency-models read-uncommitted --availability total
+    ;;
+"6b")
+    $BUILD6
+    print_wait "$1" "Totally-available, read uncommitted transactions"
+    "$MAEBIN" test -w txn-rw-register --bin "$RUSTYBIN" --node-count 2 --concurrency 2n --time-limit 20 --rate 1000 --consistency-models read-uncommitted --availability total --nemesis partition
+    ;;
+"6c")
+    $BUILD6
+    print_wait "$1" "Totally-available, read committed transactions"
+    GLOMERS_ISOLATION="read-committed" "$MAEBIN" test -w txn-rw-register --bin "$RUSTYBIN" --node-count 2 --concurrency 2n --time-limit 20 --rate 1000 --consistency-models read-committed --availability total --nemesis partition
+    ;;
+"lin-kv")
+    $BUILD6
+    print_wait "$1" "Raft linearizable key-value store"
+    GLOMERS_RAFT="1" "$MAEBIN" test -w lin-kv --bin "$RUSTYBIN" --node-count 3 --concurrency 2n --time-limit 20 --rate 100 --nemesis partition
+    ;;
+"workloads")
+    # the end to end tests in 6/tests, run against its release binary
+    $BUILD6
+    print_wait "$1" "Workload tests"
+    GLOMERS_BIN="$(realpath "$RUSTYBIN")" cargo test --manifest-path="$TXN/Cargo.toml" --test workloads
+    ;;
 *)
     echo "not valid option, run with './run.sh number'"
     ;;